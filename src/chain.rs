@@ -0,0 +1,249 @@
+use std::collections::{BTreeSet, HashMap};
+
+use failure::{err_msg, Error as FError, Fallible};
+use rand::seq::IteratorRandom;
+use rand::{CryptoRng, RngCore};
+
+use crate::config::Remailer;
+
+/// Expand the "user-defined" chain: split comma-separated remailers and, if a number of hops is
+/// given, add jokers ("*") before the chain until it is long enough.
+pub fn expand_chain(chain: &[String], hops: Option<usize>) -> Fallible<Vec<String>> {
+    let mut expanded: Vec<String> = chain
+        .iter()
+        .flat_map(|rem| rem.split(','))
+        .map(str::trim)
+        .filter(|rem| !rem.is_empty())
+        .map(String::from)
+        .collect();
+    if let Some(hops) = hops {
+        if expanded.len() > hops {
+            return Err(err_msg(format!(
+                "The chain has {} remailers, more than the {} hops wanted!",
+                expanded.len(),
+                hops
+            )));
+        }
+        let mut filled = vec!["*".to_string(); hops - expanded.len()];
+        filled.append(&mut expanded);
+        expanded = filled;
+    }
+    Ok(expanded)
+}
+
+/// Replace the named chains of a "user-defined" chain, `@name`, by the remailers they are made
+/// of (see `settings::Profile`). A named chain can use the other named chains.
+pub fn expand_named_chains(
+    chain: &[String],
+    named: &HashMap<String, Vec<String>>,
+) -> Fallible<Vec<String>> {
+    expand_named(chain, named, &mut Vec::new())
+}
+
+/// Replace the named chains, the ones being expanded being in `stack` to detect the cycles
+fn expand_named(
+    chain: &[String],
+    named: &HashMap<String, Vec<String>>,
+    stack: &mut Vec<String>,
+) -> Fallible<Vec<String>> {
+    let mut expanded = Vec::new();
+    for rem in chain.iter().flat_map(|rem| rem.split(',')).map(str::trim) {
+        let name = match rem.strip_prefix('@') {
+            Some(name) => name,
+            None => {
+                expanded.push(rem.to_string());
+                continue;
+            }
+        };
+        if stack.iter().any(|expanding| expanding == name) {
+            return Err(err_msg(format!("The named chain `@{}` uses itself!", name)));
+        }
+        let chain = named
+            .get(name)
+            .ok_or_else(|| err_msg(format!("Unknown named chain `@{}`!", name)))?;
+        stack.push(name.to_string());
+        expanded.append(&mut expand_named(chain, named, stack)?);
+        stack.pop();
+    }
+    Ok(expanded)
+}
+
+/// Return if an email is in a domain: `dizum.com` or `eu` for `remailer@dizum.eu`
+pub fn in_domain(email: &str, domain: &str) -> bool {
+    let email_domain = email.rsplit('@').next().unwrap_or("").to_lowercase();
+    let domain = domain.to_lowercase();
+    email_domain == domain || email_domain.ends_with(&format!(".{}", domain))
+}
+
+/// Select the remailers matching any of the selectors, with the syntax of the chains: `*` for
+/// the enabled remailers, `*@domain` for the enabled ones of a domain, or the alias or the email
+/// of a remailer (enabled or not). Unknown names are an error. The remailers keep their order.
+pub fn select_remailers<'a>(
+    selectors: &[String],
+    remailers: &'a [Remailer],
+) -> Fallible<Vec<&'a Remailer>> {
+    for selector in selectors {
+        if selector != "*"
+            && !selector.starts_with("*@")
+            && !remailers
+                .iter()
+                .any(|remailer| is_named(remailer, selector))
+        {
+            return Err(err_msg(format!("Unknown remailer `{}`!", selector)));
+        }
+    }
+    Ok(remailers
+        .iter()
+        .filter(|remailer| {
+            selectors.iter().any(|selector| match selector.as_str() {
+                "*" => remailer.enable,
+                selector => match selector.strip_prefix("*@") {
+                    Some(domain) => remailer.enable && in_domain(&remailer.email, domain),
+                    None => is_named(remailer, selector),
+                },
+            })
+        })
+        .collect())
+}
+
+/// Return if a remailer has this alias or this email
+fn is_named(remailer: &Remailer, name: &str) -> bool {
+    remailer.email.eq_ignore_ascii_case(name) || remailer.name.iter().any(|alias| alias == name)
+}
+
+/// Make a chain of remailers with the given "user-defined" chain.
+/// Unknown or disabled remailers are an error, unless `allow_missing` is set: they are then
+/// replaced by jokers, to keep the length of the chain.
+/// Jokers are randomly chosen between enabled remailers, if possible different from their
+/// neighbours in the chain; a `*@domain` joker is chosen between the ones of a domain (see
+/// `in_domain`).
+pub fn make_chain<R: RngCore + CryptoRng>(
+    chain: &[String],
+    remmap: &HashMap<String, String>,
+    remailers: &[Remailer],
+    allow_missing: bool,
+    rng: &mut R,
+) -> Fallible<Vec<String>> {
+    // New chain holder, jokers are kept empty until all named remailers are known; the domain
+    // of each joker, if any
+    let mut rchain: Vec<Option<String>> = Vec::new();
+    let mut domains: Vec<Option<&str>> = Vec::new();
+    let mut replaced = 0;
+    // For all remailers in the actual chain:
+    for rem in chain {
+        // Case of "randomly chosen" remailer
+        if rem == "*" {
+            rchain.push(None);
+            domains.push(None);
+        // Case of a remailer randomly chosen in a domain
+        } else if let Some(domain) = rem.strip_prefix("*@") {
+            rchain.push(None);
+            domains.push(Some(domain));
+        // Case of a named remailer
+        } else {
+            // If the remailer name is known in the map, we add it in the chain, otherwise we fail
+            // or, when allowed, replace it by a joker and print a message in the stderr
+            match remmap.get(rem) {
+                Some(email) => rchain.push(Some(email.clone())),
+                None if allow_missing => {
+                    log::warn!("Remailer `{}` replaced by a random one in the chain!", rem);
+                    rchain.push(None);
+                    replaced += 1;
+                }
+                None => return Err(unknown_remailer(rem, remmap, remailers)),
+            }
+            domains.push(None);
+        }
+    }
+
+    // Each remailer only once (the map contains its aliases), sorted to not depend on the map order
+    let emails: BTreeSet<&String> = remmap.values().collect();
+    // Randomly choose the jokers, if possible different from their neighbours
+    for index in 0..rchain.len() {
+        if rchain[index].is_none() {
+            let previous = index
+                .checked_sub(1)
+                .and_then(|previous| rchain[previous].as_ref());
+            let next = rchain.get(index + 1).and_then(Option::as_ref);
+            let candidates: Vec<&String> = emails
+                .iter()
+                .filter(|email| match domains[index] {
+                    Some(domain) => in_domain(email, domain),
+                    None => true,
+                })
+                .cloned()
+                .collect();
+            let email = candidates
+                .iter()
+                .filter(|&&email| Some(email) != previous && Some(email) != next)
+                .choose(rng)
+                .or_else(|| candidates.iter().choose(rng))
+                .ok_or_else(|| match domains[index] {
+                    Some(domain) => err_msg(format!(
+                        "No enabled remailer in the domain `{}` for `*@{}`!",
+                        domain, domain
+                    )),
+                    None => err_msg("Can't choose a remailer randomly..."),
+                })?;
+            rchain[index] = Some((*email).clone());
+        }
+    }
+    let rchain: Vec<String> = rchain.into_iter().flatten().collect();
+    if replaced > 0 {
+        log::warn!(
+            "{} missing remailer(s) replaced, the chain has {} hops: {}",
+            replaced,
+            rchain.len(),
+            rchain.join(", ")
+        );
+    }
+    // If the produced chain is empty, we make an error!
+    if rchain.is_empty() {
+        return Err(err_msg(
+            "No chain selected, use `--chain <remailer>` or `--hops <number>`",
+        ));
+    }
+    Ok(rchain)
+}
+
+/// Make the error for a remailer name missing in the map, with some close names as suggestion
+fn unknown_remailer(
+    name: &str,
+    remmap: &HashMap<String, String>,
+    remailers: &[Remailer],
+) -> FError {
+    // The remailer exists but is disabled in the config
+    if remailers
+        .iter()
+        .any(|remailer| remailer.email == name || remailer.name.iter().any(|alias| alias == name))
+    {
+        return err_msg(format!(
+            "Remailer `{}` is disabled in the config! (use --allow-missing to replace it by a random one)",
+            name
+        ));
+    }
+
+    // Otherwise, look for the closest known names (typo of one or two chars)
+    let mut suggestions: Vec<(usize, &String)> = remmap
+        .keys()
+        .map(|known| (strsim::levenshtein(name, known), known))
+        .filter(|(distance, known)| *distance <= 2.max(known.len() / 3))
+        .collect();
+    suggestions.sort();
+    match suggestions.as_slice() {
+        [] => err_msg(format!(
+            "Unknown remailer `{}` in the chain! (use --allow-missing to replace it by a random one)",
+            name
+        )),
+        suggestions => err_msg(format!(
+            "Unknown remailer `{}` in the chain! Did you mean {}?",
+            name,
+            suggestions
+                .iter()
+                .take(3)
+                .map(|(_, known)| format!("`{}`", known))
+                .collect::<Vec<String>>()
+                .join(" or ")
+        )),
+    }
+}
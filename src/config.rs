@@ -0,0 +1,1299 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use failure::{err_msg, Error, Fail, Fallible, ResultExt};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::migrate::check_supported;
+use crate::pgp::{armor, packet};
+use crate::PGPBackend;
+
+/// A representation for the JSON config needed.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug, Default)]
+pub struct RemailerConfig {
+    /// The version of the config format, see `migrate::CURRENT_SCHEMA`
+    #[serde(default = "default_schema")]
+    pub schema: u64,
+    /// The version of the remailer list, as its date
+    pub version: String,
+    pub authors: Vec<String>,
+    pub remailers: Vec<Remailer>,
+}
+
+/// A representation for a remailer value in the JSON config needed
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug, Default)]
+pub struct Remailer {
+    pub name: Vec<String>,
+    pub email: String,
+    pub enable: bool,
+    pub key: String,
+    /// The remailer capabilities, as in its `remailer-conf` (`cpunk`, `pgp`, `latent`...)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+    /// The expected fingerprint of the key; a key not matching it is refused
+    #[serde(
+        default,
+        rename = "fingerprint",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub pinned_fingerprint: Option<String>,
+    /// The statistics of the remailer, from the last pinger report read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<RemailerStats>,
+    /// The directory of the config, where the `file:` and `dir:` keys are looked for; the
+    /// working directory if none
+    #[serde(skip)]
+    pub base_dir: Option<PathBuf>,
+}
+
+/// The statistics of a remailer, as published by the pingers
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug, Default)]
+pub struct RemailerStats {
+    /// The usual delay of the remailer, as `H:MM`
+    pub latency: String,
+    /// The share of the pings delivered, as `99.95%`
+    pub uptime: String,
+}
+
+/// The encoding of a remailer key in the config, detected from its prefix
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum KeyEncoding<'a> {
+    /// `base64:<data>`, binary key data encoded in base64
+    Base64(&'a str),
+    /// `-----BEGIN PGP PUBLIC KEY BLOCK-----...`, an ASCII-armored key
+    Armored(&'a str),
+    /// `file:<path>`, a key file (armored or binary), relative to the config
+    File(&'a str),
+    /// `dir:<path>`, a directory of `<email>.asc` key files, relative to the config
+    Dir(&'a str),
+}
+
+impl<'a> KeyEncoding<'a> {
+    /// Detect the encoding of a key from the config
+    pub fn detect(key: &'a str) -> Option<Self> {
+        if let Some(data) = key.strip_prefix("base64:") {
+            Some(KeyEncoding::Base64(data))
+        } else if let Some(path) = key.strip_prefix("file:") {
+            Some(KeyEncoding::File(path))
+        } else if let Some(path) = key.strip_prefix("dir:") {
+            Some(KeyEncoding::Dir(path))
+        } else if key
+            .trim_start()
+            .starts_with("-----BEGIN PGP PUBLIC KEY BLOCK-----")
+        {
+            Some(KeyEncoding::Armored(key))
+        } else {
+            None
+        }
+    }
+}
+
+/// A problem with the key of a remailer, named by its label
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum KeyError {
+    Empty(String),
+    UnknownEncoding(String),
+    InvalidBase64(String, String),
+    Unreadable(String, PathBuf, String),
+    InvalidKey(String, String),
+    FingerprintMismatch(String, String, String),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::Empty(name) => write!(f, "The key of `{}` is empty", name),
+            KeyError::UnknownEncoding(name) => write!(
+                f,
+                "The key of `{}` has an unknown encoding, expected `base64:<data>`, an armored key, `file:<path>` or `dir:<path>`",
+                name
+            ),
+            KeyError::InvalidBase64(name, reason) => {
+                write!(f, "The key of `{}` is not valid base64: {}", name, reason)
+            }
+            KeyError::Unreadable(name, path, reason) => write!(
+                f,
+                "The key of `{}` can't be read from {:?}: {}",
+                name, path, reason
+            ),
+            KeyError::InvalidKey(name, reason) => {
+                write!(f, "The key of `{}` is not an OpenPGP key: {}", name, reason)
+            }
+            KeyError::FingerprintMismatch(name, expected, actual) => write!(
+                f,
+                "The key of `{}` has the fingerprint {}, not the expected {}",
+                name, actual, expected
+            ),
+        }
+    }
+}
+
+impl Fail for KeyError {}
+
+/// A problem in a remailer config; the JSON path (`$.remailers[0].key`) locates it
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum ConfigError {
+    Read {
+        file: String,
+        reason: String,
+    },
+    Syntax {
+        file: String,
+        format: ConfigFormat,
+        /// The line and the column of the error, when known
+        position: Option<(usize, usize)>,
+        reason: String,
+    },
+    Missing {
+        path: String,
+    },
+    Type {
+        path: String,
+        expected: &'static str,
+    },
+    EmptyNames {
+        path: String,
+    },
+    InvalidEmail {
+        path: String,
+        email: String,
+    },
+    DuplicateAlias {
+        path: String,
+        alias: String,
+        first: String,
+    },
+    DuplicateEmail {
+        path: String,
+        email: String,
+        first: String,
+    },
+    Key {
+        path: String,
+        error: KeyError,
+    },
+    Unsigned {
+        file: String,
+    },
+    BadSignature {
+        file: String,
+        reason: String,
+    },
+    /// A `file:` or `dir:` key in a config which must be signed, without pinned fingerprint:
+    /// the key files are not covered by the signature
+    UnpinnedKey {
+        file: String,
+        path: String,
+    },
+    TooNew {
+        file: String,
+        schema: u64,
+        supported: u64,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { file, reason } => {
+                write!(f, "Cannot read the config {}: {}", file, reason)
+            }
+            ConfigError::Syntax {
+                file,
+                format,
+                position: Some((line, column)),
+                reason,
+            } => write!(
+                f,
+                "Invalid {} in {} at line {}, column {}: {}",
+                format, file, line, column, reason
+            ),
+            ConfigError::Syntax {
+                file,
+                format,
+                position: None,
+                reason,
+            } => write!(f, "Invalid {} in {}: {}", format, file, reason),
+            ConfigError::Missing { path } => write!(f, "{}: missing field", path),
+            ConfigError::Type { path, expected } => write!(f, "{}: expected {}", path, expected),
+            ConfigError::EmptyNames { path } => write!(f, "{}: empty name list", path),
+            ConfigError::InvalidEmail { path, email } => {
+                write!(f, "{}: invalid email `{}`", path, email)
+            }
+            ConfigError::DuplicateAlias { path, alias, first } => {
+                write!(
+                    f,
+                    "{}: alias `{}` is already used at {}",
+                    path, alias, first
+                )
+            }
+            ConfigError::DuplicateEmail { path, email, first } => {
+                write!(
+                    f,
+                    "{}: email `{}` is already used at {}",
+                    path, email, first
+                )
+            }
+            ConfigError::Key { path, error } => write!(f, "{}: {}", path, error),
+            ConfigError::Unsigned { file } => write!(
+                f,
+                "The config {} is not signed (no {}.sig or {}.asc)",
+                file, file, file
+            ),
+            ConfigError::TooNew {
+                file,
+                schema,
+                supported,
+            } => write!(
+                f,
+                "The config {} has the schema version {}, this version of cypherpunk-cli only supports up to {}: please upgrade it",
+                file, schema, supported
+            ),
+            ConfigError::UnpinnedKey { file, path } => write!(
+                f,
+                "{}: {}: a `file:` or `dir:` key needs a `fingerprint` in a signed config, its signature doesn't cover the key files",
+                file, path
+            ),
+            ConfigError::BadSignature { file, reason } => {
+                write!(
+                    f,
+                    "The signature of the config {} is not valid: {}",
+                    file, reason
+                )
+            }
+        }
+    }
+}
+
+impl Fail for ConfigError {}
+
+impl Remailer {
+    /// Return the name of this remailer for messages: its first alias, or its email
+    pub fn label(&self) -> &str {
+        self.name.first().unwrap_or(&self.email)
+    }
+
+    /// Return if this remailer is enable in the config
+    pub fn is_enabled(&self) -> bool {
+        self.enable
+    }
+
+    /// Return if this remailer has the given capability (`inflt` matches `inflt50`).
+    /// Without any known capability, the remailer is supposed capable of everything.
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.is_empty()
+            || self
+                .capabilities
+                .iter()
+                .any(|known| known.starts_with(capability))
+    }
+
+    /// Return and decode the key of this remailer, as binary OpenPGP data, checking its pinned
+    /// fingerprint if any
+    pub fn as_key(&self) -> Result<Vec<u8>, KeyError> {
+        let key = self.decode_key()?;
+        if let Some(expected) = &self.pinned_fingerprint {
+            let actual = packet::fingerprint(&key)
+                .map_err(|err| KeyError::InvalidKey(self.label().to_string(), err.to_string()))?;
+            if !actual.eq_ignore_ascii_case(&expected.replace(' ', "")) {
+                return Err(KeyError::FingerprintMismatch(
+                    self.label().to_string(),
+                    expected.clone(),
+                    actual,
+                ));
+            }
+        }
+        Ok(key)
+    }
+
+    /// Replace a `file:` or `dir:` key by its data, as `base64:`: the key files are relative to
+    /// the config the remailer was read from, not to the one it's written in
+    pub fn inline_key(&mut self) -> Result<(), KeyError> {
+        if let Some(KeyEncoding::File(_)) | Some(KeyEncoding::Dir(_)) =
+            KeyEncoding::detect(&self.key)
+        {
+            self.key = format!("base64:{}", base64::encode(&self.as_key()?));
+            self.base_dir = None;
+        }
+        Ok(())
+    }
+
+    /// Decode the key of this remailer, from any encoding
+    fn decode_key(&self) -> Result<Vec<u8>, KeyError> {
+        let label = || self.label().to_string();
+        if self.key.trim().is_empty() {
+            return Err(KeyError::Empty(label()));
+        }
+        let key = match KeyEncoding::detect(&self.key) {
+            Some(KeyEncoding::Base64(data)) => {
+                return base64::decode(data.trim())
+                    .map_err(|err| KeyError::InvalidBase64(label(), err.to_string()))
+            }
+            Some(KeyEncoding::Armored(text)) => text.as_bytes().to_vec(),
+            Some(KeyEncoding::File(path)) => self.read_key(&self.key_path(path))?,
+            Some(KeyEncoding::Dir(path)) => {
+                let dir = self.key_path(path);
+                let exact = dir.join(format!("{}.asc", self.email));
+                let lower = dir.join(format!("{}.asc", self.email.to_lowercase()));
+                self.read_key(if exact.is_file() || !lower.is_file() {
+                    &exact
+                } else {
+                    &lower
+                })?
+            }
+            None => return Err(KeyError::UnknownEncoding(label())),
+        };
+        armor::dearmor(&key).map_err(|err| KeyError::InvalidKey(label(), err.to_string()))
+    }
+
+    /// Return the path of a key file, relative to the config
+    fn key_path(&self, path: &str) -> PathBuf {
+        match &self.base_dir {
+            Some(base_dir) => base_dir.join(path.trim()),
+            None => PathBuf::from(path.trim()),
+        }
+    }
+
+    /// Read a key file
+    fn read_key(&self, path: &Path) -> Result<Vec<u8>, KeyError> {
+        fs::read(path).map_err(|err| {
+            KeyError::Unreadable(
+                self.label().to_string(),
+                path.to_path_buf(),
+                err.to_string(),
+            )
+        })
+    }
+
+    /// Return the fingerprint of the key of this remailer
+    pub fn fingerprint(&self) -> Fallible<String> {
+        packet::fingerprint(&self.as_key()?)
+            .map_err(|err| KeyError::InvalidKey(self.label().to_string(), err.to_string()).into())
+    }
+}
+
+/// The schema version of the configs without any, the first one
+fn default_schema() -> u64 {
+    1
+}
+
+/// The remailer list shipped with the binary, the last-resort config
+pub const EMBEDDED_CONFIG: &str = include_str!("../remailers.json");
+
+/// The file name of the config in each config directory
+pub const CONFIG_FILE: &str = "remailers.json";
+
+/// The other file names of the config, looked for when there is no `CONFIG_FILE`
+pub const CONFIG_ALTERNATIVES: &[&str] = &["remailers.toml", "remailers.yaml", "remailers.yml"];
+
+/// The syntax of a config file. They are all read into the same JSON value, so the schema, the
+/// checks and the migrations are the same for every format.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConfigFormat::Json => "JSON",
+            ConfigFormat::Toml => "TOML",
+            ConfigFormat::Yaml => "YAML",
+        })
+    }
+}
+
+impl ConfigFormat {
+    /// Detect the format of a config by the extension of its file, or else by its content
+    pub fn detect(path: &Path, text: &str) -> Self {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("json") => ConfigFormat::Json,
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => Self::sniff(text),
+        }
+    }
+
+    /// Guess the format of a config by its first meaningful line: an object is JSON, a table or
+    /// a `key = value` is TOML, anything else is YAML
+    pub fn sniff(text: &str) -> Self {
+        let line = text
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .unwrap_or("");
+        if line.starts_with('{') {
+            ConfigFormat::Json
+        } else if line.starts_with('[') {
+            ConfigFormat::Toml
+        } else {
+            match (line.find('='), line.find(':')) {
+                (Some(equal), Some(colon)) if equal < colon => ConfigFormat::Toml,
+                (Some(_), None) => ConfigFormat::Toml,
+                _ => ConfigFormat::Yaml,
+            }
+        }
+    }
+
+    /// Parse a config written in this format
+    pub fn parse(self, text: &str, file: &str) -> Result<Value, ConfigError> {
+        let (position, reason) = match self {
+            ConfigFormat::Json => match serde_json::from_str(text) {
+                Ok(value) => return Ok(value),
+                Err(err) => (Some((err.line(), err.column())), err.to_string()),
+            },
+            ConfigFormat::Toml => match toml::from_str(text) {
+                Ok(value) => return Ok(value),
+                Err(err) => (
+                    err.line_col().map(|(line, column)| (line + 1, column + 1)),
+                    err.to_string(),
+                ),
+            },
+            ConfigFormat::Yaml => match serde_yaml::from_str(text) {
+                Ok(value) => return Ok(value),
+                Err(err) => (
+                    err.location()
+                        .map(|location| (location.line(), location.column())),
+                    err.to_string(),
+                ),
+            },
+        };
+        Err(ConfigError::Syntax {
+            file: file.to_string(),
+            format: self,
+            position,
+            reason,
+        })
+    }
+
+    /// Write a config in this format; the comments of the original file are not kept
+    pub fn serialize(self, config: &Value) -> Fallible<String> {
+        let mut text = match self {
+            ConfigFormat::Json => serde_json::to_string_pretty(config)?,
+            ConfigFormat::Toml => toml::to_string_pretty(&toml::Value::try_from(config)?)?,
+            ConfigFormat::Yaml => serde_yaml::to_string(config)?,
+        };
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        Ok(text)
+    }
+}
+
+/// The directory of the trusted list-signer keys, in each config directory
+pub const SIGNERS_DIR: &str = "signers";
+
+/// The extensions of the detached signature of a config, appended to its file name
+pub const SIGNATURE_EXTENSIONS: &[&str] = &["sig", "asc"];
+
+/// A layer of the config, read from a file or embedded in the binary. A layer with a `version`
+/// is a complete remailer list, replacing the layers below it; without, it's an overlay.
+#[derive(Clone, Debug)]
+pub struct ConfigLayer {
+    /// Where the layer comes from: its path, or `embedded`
+    pub origin: String,
+    /// The file of the layer, none if embedded
+    pub path: Option<PathBuf>,
+    /// The bytes of the layer as read: the ones verified against its signature, then parsed
+    pub data: Vec<u8>,
+    pub value: Value,
+}
+
+impl ConfigLayer {
+    /// Read a layer file, only once: its value is parsed from the bytes kept for its signature,
+    /// its relative `file:` and `dir:` keys being resolved against its directory. A layer newer
+    /// than this version of cypherpunk-cli is refused (see `check_supported`).
+    pub fn read(path: &Path) -> Result<ConfigLayer, ConfigError> {
+        let file = path.to_string_lossy().into_owned();
+        let data = fs::read(path).map_err(|err| ConfigError::Read {
+            file: file.clone(),
+            reason: err.to_string(),
+        })?;
+        let (mut value, _) = parse_config(path, &data)?;
+        check_supported(&value, &file)?;
+        resolve_key_paths(&mut value, &config_dir(path));
+        Ok(ConfigLayer {
+            origin: file,
+            path: Some(path.to_path_buf()),
+            data,
+            value,
+        })
+    }
+
+    /// Return if the layer is a complete remailer list
+    pub fn is_complete(&self) -> bool {
+        self.value.get("version").is_some()
+    }
+
+    /// Return if the layer can change the remailers' keys: a complete list, or an overlay
+    /// giving keys or pinned fingerprints
+    pub fn sets_keys(&self) -> bool {
+        self.is_complete()
+            || self.value["remailers"].as_array().is_some_and(|remailers| {
+                remailers.iter().any(|remailer| {
+                    remailer.get("key").is_some() || remailer.get("fingerprint").is_some()
+                })
+            })
+    }
+
+    /// Return the JSON path of each `file:` or `dir:` key of the layer without pinned
+    /// fingerprint
+    pub fn unpinned_key_files(&self) -> Vec<String> {
+        let remailers = match self.value["remailers"].as_array() {
+            Some(remailers) => remailers,
+            None => return Vec::new(),
+        };
+        remailers
+            .iter()
+            .enumerate()
+            .filter(|(_, remailer)| {
+                let encoding = remailer["key"].as_str().and_then(KeyEncoding::detect);
+                matches!(
+                    encoding,
+                    Some(KeyEncoding::File(_)) | Some(KeyEncoding::Dir(_))
+                ) && remailer.get("fingerprint").is_none()
+            })
+            .map(|(index, _)| format!("$.remailers[{}].key", index))
+            .collect()
+    }
+
+    /// Return the detached signature of the layer file, next to it (`remailers.json.sig` or
+    /// `remailers.json.asc`), if any
+    pub fn signature_path(&self) -> Option<PathBuf> {
+        let path = self.path.as_ref()?;
+        SIGNATURE_EXTENSIONS
+            .iter()
+            .map(|extension| {
+                let mut name = path.as_os_str().to_os_string();
+                name.push(".");
+                name.push(extension);
+                PathBuf::from(name)
+            })
+            .find(|signature| signature.is_file())
+    }
+}
+
+/// Return the config directories, from the lowest precedence to the highest: the system one,
+/// then the user one
+pub fn config_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from("/etc/cypherpunk")];
+    let user_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from));
+    if let Some(dir) = user_dir {
+        dirs.push(dir.join("cypherpunk"));
+    }
+    dirs
+}
+
+/// Return the config files searched, from the lowest precedence to the highest: the system
+/// config, the user config, then the working directory
+pub fn config_paths() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = config_dirs().iter().map(|dir| config_file(dir)).collect();
+    paths.push(config_file(Path::new(".")));
+    paths
+}
+
+/// Return the config file of a directory: `CONFIG_FILE`, or else the first of
+/// `CONFIG_ALTERNATIVES` found
+pub fn config_file(dir: &Path) -> PathBuf {
+    std::iter::once(&CONFIG_FILE)
+        .chain(CONFIG_ALTERNATIVES)
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| dir.join(CONFIG_FILE))
+}
+
+/// Return the key files of the trusted list signers, in the `signers` directory of each config
+/// directory
+pub fn signer_key_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for dir in config_dirs() {
+        if let Ok(entries) = fs::read_dir(dir.join(SIGNERS_DIR)) {
+            let mut keys: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|extension| extension == "asc" || extension == "gpg")
+                })
+                .collect();
+            keys.sort();
+            paths.append(&mut keys);
+        }
+    }
+    paths
+}
+
+/// Verify the detached signatures of the layers able to change the remailers' keys (see
+/// `ConfigLayer::sets_keys`), with a PGP backend holding only the keys of the trusted list
+/// signers. The bytes verified are the ones the layer was parsed from, not the file as it is
+/// now. Their `file:` and `dir:` keys, which the signature doesn't cover, must have a pinned
+/// fingerprint. The embedded list, and the layers read identical to it, are trusted. Return
+/// every problem found.
+pub fn verify_layers(layers: &[ConfigLayer], pgp: &dyn PGPBackend) -> Vec<ConfigError> {
+    let mut errors = Vec::new();
+    for layer in layers.iter().filter(|layer| layer.sets_keys()) {
+        let file = match &layer.path {
+            Some(path) => path.to_string_lossy().into_owned(),
+            None => continue,
+        };
+        if layer.data == EMBEDDED_CONFIG.as_bytes() {
+            continue;
+        }
+        for path in layer.unpinned_key_files() {
+            errors.push(ConfigError::UnpinnedKey {
+                file: file.clone(),
+                path,
+            });
+        }
+        let signature = match layer.signature_path() {
+            Some(signature) => signature,
+            None => {
+                errors.push(ConfigError::Unsigned { file });
+                continue;
+            }
+        };
+        let result = fs::read(&signature)
+            .map_err(Error::from)
+            .and_then(|signature| pgp.verify(&layer.data, &signature));
+        if let Err(err) = result {
+            errors.push(ConfigError::BadSignature {
+                file,
+                reason: err.to_string(),
+            });
+        }
+    }
+    errors
+}
+
+/// Read the layers of the config: the embedded list, the existing files among `paths`, then
+/// the `explicit` file which must exist. The layers are not upgraded to the current schema
+/// version (see `ConfigLayer::read`).
+pub fn load_layers(
+    paths: &[PathBuf],
+    explicit: Option<&Path>,
+) -> Result<Vec<ConfigLayer>, ConfigError> {
+    let embedded = ConfigFormat::Json.parse(EMBEDDED_CONFIG, "embedded")?;
+    let mut layers = vec![ConfigLayer {
+        origin: "embedded".to_string(),
+        path: None,
+        data: EMBEDDED_CONFIG.as_bytes().to_vec(),
+        value: embedded,
+    }];
+    let files = paths
+        .iter()
+        .filter(|path| path.is_file())
+        .map(PathBuf::as_path)
+        .chain(explicit);
+    check_supported(&layers[0].value, "embedded")?;
+    for path in files {
+        layers.push(ConfigLayer::read(path)?);
+    }
+    Ok(layers)
+}
+
+/// Merge the layers of the config, from the lowest precedence to the highest. The last complete
+/// list is the base; each overlay after it sets fields of the remailers matched by email (their
+/// aliases are added to the known ones), or adds new remailers.
+pub fn merge_layers(layers: &[ConfigLayer]) -> Value {
+    let base = base_layer(layers);
+    let mut merged = layers
+        .get(base)
+        .map(|layer| layer.value.clone())
+        .unwrap_or_else(|| Value::Object(Default::default()));
+    for layer in layers.iter().skip(base + 1) {
+        merge_overlay(&mut merged, &layer.value);
+    }
+    merged
+}
+
+/// Return the index of the base layer of the config: the last complete list
+fn base_layer(layers: &[ConfigLayer]) -> usize {
+    layers
+        .iter()
+        .rposition(ConfigLayer::is_complete)
+        .unwrap_or(0)
+}
+
+/// Load the config merged from its layers (see `merge_layers`), refusing the duplicate aliases
+/// and emails (see `check_duplicates`)
+pub fn load_layered_config(layers: &[ConfigLayer]) -> Fallible<RemailerConfig> {
+    let value = merge_layers(layers);
+    if let Some(error) = check_schema(&value).into_iter().next() {
+        return Err(error.context("Invalid merged config!").into());
+    }
+    let config: RemailerConfig =
+        serde_json::from_value(value).context("Failed to parse the merged config!")?;
+    if let Some(error) = check_duplicates(&config.remailers).into_iter().next() {
+        return Err(error.context("Invalid merged config!").into());
+    }
+    Ok(config)
+}
+
+/// Merge an overlay into a config value
+fn merge_overlay(config: &mut Value, overlay: &Value) {
+    let (config, overlay) = match (config.as_object_mut(), overlay.as_object()) {
+        (Some(config), Some(overlay)) => (config, overlay),
+        _ => return,
+    };
+    for (field, value) in overlay {
+        if field != "remailers" {
+            config.insert(field.clone(), value.clone());
+        }
+    }
+    let overlays = match overlay.get("remailers").and_then(Value::as_array) {
+        Some(overlays) => overlays,
+        None => return,
+    };
+    let remailers = config
+        .entry("remailers")
+        .or_insert_with(|| Value::Array(Vec::new()));
+    let remailers = match remailers.as_array_mut() {
+        Some(remailers) => remailers,
+        None => return,
+    };
+    for overlay in overlays {
+        let email = overlay
+            .get("email")
+            .and_then(Value::as_str)
+            .map(str::to_lowercase);
+        let existing = remailers.iter_mut().find(|remailer| {
+            let known = remailer
+                .get("email")
+                .and_then(Value::as_str)
+                .map(str::to_lowercase);
+            email.is_some() && known == email
+        });
+        match (existing.and_then(Value::as_object_mut), overlay.as_object()) {
+            (Some(remailer), Some(overlay)) => {
+                for (field, value) in overlay {
+                    match (field.as_str(), remailer.get_mut(field), value.as_array()) {
+                        // Add the aliases
+                        ("name", Some(Value::Array(names)), Some(aliases)) => {
+                            for alias in aliases {
+                                if !names.contains(alias) {
+                                    names.push(alias.clone());
+                                }
+                            }
+                        }
+                        _ => {
+                            remailer.insert(field.clone(), value.clone());
+                        }
+                    }
+                }
+            }
+            _ => remailers.push(overlay.clone()),
+        }
+    }
+}
+
+/// Enable or disable a remailer of a config value, matched by email. A remailer missing from an
+/// overlay is added to it, as `{"email": ..., "enable": ...}`; from a complete list, it's an error.
+pub fn set_enable(config: &mut Value, email: &str, enable: bool) -> Fallible<()> {
+    let complete = config.get("version").is_some();
+    let object = config
+        .as_object_mut()
+        .ok_or_else(|| err_msg("The config is not an object"))?;
+    let remailers = object
+        .entry("remailers")
+        .or_insert_with(|| Value::Array(Vec::new()))
+        .as_array_mut()
+        .ok_or_else(|| err_msg("The `remailers` of the config are not an array"))?;
+    let existing = remailers.iter_mut().find(|remailer| {
+        remailer
+            .get("email")
+            .and_then(Value::as_str)
+            .is_some_and(|known| known.eq_ignore_ascii_case(email))
+    });
+    match existing.and_then(Value::as_object_mut) {
+        Some(remailer) => {
+            remailer.insert("enable".to_string(), Value::Bool(enable));
+        }
+        None if complete => {
+            return Err(err_msg(format!(
+                "The remailer `{}` is not in the remailer list",
+                email
+            )))
+        }
+        None => remailers.push(serde_json::json!({ "email": email, "enable": enable })),
+    }
+    Ok(())
+}
+
+/// Make the relative `file:` and `dir:` keys of a config relative to its directory instead
+fn resolve_key_paths(config: &mut Value, dir: &Path) {
+    let remailers = match config.get_mut("remailers").and_then(Value::as_array_mut) {
+        Some(remailers) => remailers,
+        None => return,
+    };
+    for key in remailers
+        .iter_mut()
+        .filter_map(|remailer| remailer.get_mut("key"))
+    {
+        let resolved = match key.as_str().and_then(KeyEncoding::detect) {
+            Some(KeyEncoding::File(path)) if Path::new(path.trim()).is_relative() => {
+                format!("file:{}", dir.join(path.trim()).to_string_lossy())
+            }
+            Some(KeyEncoding::Dir(path)) if Path::new(path.trim()).is_relative() => {
+                format!("dir:{}", dir.join(path.trim()).to_string_lossy())
+            }
+            _ => continue,
+        };
+        *key = Value::String(resolved);
+    }
+}
+
+/// Retrieve from path given the remailer config, as written: a config newer than this version
+/// of cypherpunk-cli is refused (see `check_supported`), the older ones are not upgraded. A
+/// config not matching the schema (see `check_schema`) or with duplicate aliases or emails (see
+/// `check_duplicates`) is refused with the first `ConfigError` found
+pub fn load_config<P: AsRef<Path>>(path: P) -> Fallible<RemailerConfig> {
+    let base_dir = config_dir(path.as_ref());
+    let value = read_config(path.as_ref())?;
+    check_supported(&value, &path.as_ref().to_string_lossy())?;
+    if let Some(error) = check_schema(&value).into_iter().next() {
+        return Err(error
+            .context(format!("Invalid config {:?}!", path.as_ref()))
+            .into());
+    }
+    let mut config: RemailerConfig = serde_json::from_value(value)
+        .context(format!("Failed to parse {:?} as a config!", path.as_ref()))?;
+    if let Some(error) = check_duplicates(&config.remailers).into_iter().next() {
+        return Err(error
+            .context(format!("Invalid config {:?}!", path.as_ref()))
+            .into());
+    }
+    for remailer in &mut config.remailers {
+        remailer.base_dir = Some(base_dir.clone());
+    }
+    Ok(config)
+}
+
+/// Replace a config file, in the format it's written in, keeping a copy of it; return the path of
+/// the copy
+pub fn write_config(path: &Path, config: &Value) -> Fallible<PathBuf> {
+    let text = fs::read_to_string(path).context(format!("Cannot read the config {:?}", path))?;
+    let format = ConfigFormat::detect(path, &text);
+    let mut backup = path.as_os_str().to_os_string();
+    backup.push(".bak");
+    fs::write(&backup, text).context(format!("Cannot copy {:?} to {:?}", path, backup))?;
+    let text = format
+        .serialize(config)
+        .context(format!("Cannot write the config as {}", format))?;
+    fs::write(path, text).context(format!("Cannot write the config {:?}", path))?;
+    Ok(PathBuf::from(backup))
+}
+
+/// Return the directory of a config file, where its key files are looked for
+pub fn config_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Read a config file as a JSON value, whatever its format (see `ConfigFormat`)
+pub fn read_config(path: &Path) -> Result<Value, ConfigError> {
+    read_config_format(path).map(|(value, _)| value)
+}
+
+/// Read a config file as a JSON value, and return the format it's written in
+pub fn read_config_format(path: &Path) -> Result<(Value, ConfigFormat), ConfigError> {
+    let data = fs::read(path).map_err(|err| ConfigError::Read {
+        file: path.to_string_lossy().into_owned(),
+        reason: err.to_string(),
+    })?;
+    parse_config(path, &data)
+}
+
+/// Parse the bytes of a config file as a JSON value, and return the format it's written in
+fn parse_config(path: &Path, data: &[u8]) -> Result<(Value, ConfigFormat), ConfigError> {
+    let file = path.to_string_lossy().into_owned();
+    let text = std::str::from_utf8(data).map_err(|err| ConfigError::Read {
+        file: file.clone(),
+        reason: err.to_string(),
+    })?;
+    let format = ConfigFormat::detect(path, text);
+    Ok((format.parse(text, &file)?, format))
+}
+
+/// Check that a config value matches the schema of `RemailerConfig`: the JSON types, the
+/// required fields. Return every problem found.
+pub fn check_schema(config: &Value) -> Vec<ConfigError> {
+    let mut errors = Vec::new();
+    let fields = match config.as_object() {
+        Some(fields) => fields,
+        None => {
+            errors.push(type_error("$", "an object"));
+            return errors;
+        }
+    };
+    if let Some(schema) = fields.get("schema") {
+        check_field(
+            &mut errors,
+            Some(schema),
+            "$.schema",
+            Value::is_u64,
+            "an integer",
+        );
+    }
+    check_field(
+        &mut errors,
+        fields.get("version"),
+        "$.version",
+        Value::is_string,
+        "a string",
+    );
+    check_field(
+        &mut errors,
+        fields.get("authors"),
+        "$.authors",
+        is_string_list,
+        "a list of strings",
+    );
+    let remailers = match fields.get("remailers") {
+        Some(Value::Array(remailers)) => remailers,
+        Some(_) => {
+            errors.push(type_error("$.remailers", "a list"));
+            return errors;
+        }
+        None => {
+            errors.push(ConfigError::Missing {
+                path: "$.remailers".to_string(),
+            });
+            return errors;
+        }
+    };
+
+    for (index, remailer) in remailers.iter().enumerate() {
+        check_remailer_schema(&mut errors, remailer, index, false);
+    }
+    errors
+}
+
+/// Check that an overlay matches the schema of `RemailerConfig` for the fields it gives: its
+/// remailers are matched by their email, their other fields are optional. Return every problem
+/// found.
+pub fn check_overlay_schema(config: &Value) -> Vec<ConfigError> {
+    let mut errors = Vec::new();
+    let fields = match config.as_object() {
+        Some(fields) => fields,
+        None => {
+            errors.push(type_error("$", "an object"));
+            return errors;
+        }
+    };
+    match fields.get("remailers") {
+        Some(Value::Array(remailers)) => {
+            for (index, remailer) in remailers.iter().enumerate() {
+                check_remailer_schema(&mut errors, remailer, index, true);
+            }
+        }
+        Some(_) => errors.push(type_error("$.remailers", "a list")),
+        None => {}
+    }
+    errors
+}
+
+/// A field of a remailer in the schema: its name, its check, the expected type, and if it's
+/// required
+type FieldSchema = (&'static str, fn(&Value) -> bool, &'static str, bool);
+
+/// The fields of a remailer in the schema of `RemailerConfig`
+const REMAILER_SCHEMA: [FieldSchema; 7] = [
+    ("name", is_string_list, "a list of strings", true),
+    ("email", Value::is_string, "a string", true),
+    ("enable", Value::is_boolean, "a boolean", true),
+    ("key", Value::is_string, "a string", true),
+    ("capabilities", is_string_list, "a list of strings", false),
+    (
+        "stats",
+        is_stats,
+        "an object with a `latency` and an `uptime` string",
+        false,
+    ),
+    ("fingerprint", Value::is_string, "a string", false),
+];
+
+/// Check the fields of the remailer at `index` of a config: the ones required by the schema,
+/// only the email for an `overlay`, and the optional ones given
+fn check_remailer_schema(
+    errors: &mut Vec<ConfigError>,
+    remailer: &Value,
+    index: usize,
+    overlay: bool,
+) {
+    let path = format!("$.remailers[{}]", index);
+    let fields = match remailer.as_object() {
+        Some(fields) => fields,
+        None => {
+            errors.push(type_error(&path, "an object"));
+            return;
+        }
+    };
+    for (field, is_valid, expected, required) in REMAILER_SCHEMA.iter() {
+        let value = fields.get(*field);
+        if value.is_some() || (*required && (!overlay || *field == "email")) {
+            check_field(
+                errors,
+                value,
+                &format!("{}.{}", path, field),
+                *is_valid,
+                expected,
+            );
+        }
+    }
+}
+
+/// Check a config against its schema (see `check_schema`), then check its remailers matching
+/// the schema: non-empty name lists, valid emails, unique aliases and emails, and decodable
+/// keys for the enabled ones (the key files are looked for in `base_dir`). Return every problem
+/// found.
+pub fn check_config(config: &Value, base_dir: &Path) -> Vec<ConfigError> {
+    let mut errors = check_schema(config);
+    check_remailers(
+        config,
+        Some(base_dir),
+        false,
+        &mut Uses::default(),
+        &mut errors,
+    );
+    errors
+}
+
+/// Check the layers used by the merged config (see `merge_layers`), each one with its own JSON
+/// paths: the complete list as by `check_config`, the overlays for the fields they give. The
+/// aliases and emails must be unique across the layers. Return every problem found, with the
+/// origin of its layer.
+pub fn check_layers(layers: &[ConfigLayer]) -> Vec<(String, ConfigError)> {
+    let mut uses = Uses::default();
+    let mut found = Vec::new();
+    for layer in layers.iter().skip(base_layer(layers)) {
+        let overlay = !layer.is_complete();
+        let mut errors = if overlay {
+            check_overlay_schema(&layer.value)
+        } else {
+            check_schema(&layer.value)
+        };
+        uses.origin = Some(layer.origin.clone());
+        // The key files of a layer are already resolved against its directory
+        check_remailers(&layer.value, None, overlay, &mut uses, &mut errors);
+        found.extend(
+            errors
+                .into_iter()
+                .map(|error| (layer.origin.clone(), error)),
+        );
+    }
+    found
+}
+
+/// Check the remailers of a config matching the schema (see `check_config`), their aliases and
+/// emails being recorded in `uses`. The remailers of an `overlay` are checked for the fields
+/// they give, and can add aliases to the remailers of the layers before.
+fn check_remailers(
+    config: &Value,
+    base_dir: Option<&Path>,
+    overlay: bool,
+    uses: &mut Uses,
+    errors: &mut Vec<ConfigError>,
+) {
+    let remailers = config["remailers"].as_array().cloned().unwrap_or_default();
+    for (index, value) in remailers.into_iter().enumerate() {
+        let given = |field: &str| !overlay || value.get(field).is_some();
+        // The fields missing from an overlay are left to the layers before
+        let mut fields = serde_json::json!({ "name": [], "enable": false, "key": "" });
+        if let (Some(fields), Some(given)) = (fields.as_object_mut(), value.as_object()) {
+            fields.extend(given.clone());
+        }
+        let remailer = match serde_json::from_value(fields) {
+            Ok(remailer) => Remailer {
+                base_dir: base_dir.map(Path::to_path_buf),
+                ..remailer
+            },
+            Err(_) => continue,
+        };
+        let path = format!("$.remailers[{}]", index);
+        if remailer.name.is_empty() && given("name") {
+            errors.push(ConfigError::EmptyNames {
+                path: format!("{}.name", path),
+            });
+        }
+        if !remailer.email.contains('@') {
+            errors.push(ConfigError::InvalidEmail {
+                path: format!("{}.email", path),
+                email: remailer.email.clone(),
+            });
+        }
+        uses.check(&remailer, &path, overlay, errors);
+        let checked = if overlay {
+            given("key")
+        } else {
+            remailer.enable
+        };
+        if !checked {
+            continue;
+        }
+        if let Err(error) = remailer.fingerprint() {
+            let error = match error.downcast::<KeyError>() {
+                Ok(error) => error,
+                Err(error) => KeyError::InvalidKey(remailer.label().to_string(), error.to_string()),
+            };
+            errors.push(ConfigError::Key {
+                path: format!("{}.key", path),
+                error,
+            });
+        }
+    }
+}
+
+/// Check that the aliases and emails of remailers are unique, an email being no alias of another
+/// remailer: two remailers using the same name can't be told apart in a chain
+pub fn check_duplicates(remailers: &[Remailer]) -> Vec<ConfigError> {
+    let mut errors = Vec::new();
+    let mut uses = Uses::default();
+    for (index, remailer) in remailers.iter().enumerate() {
+        uses.check(
+            remailer,
+            &format!("$.remailers[{}]", index),
+            false,
+            &mut errors,
+        );
+    }
+    errors
+}
+
+/// The first use of each alias (with the lowercase email of its remailer) and of each
+/// (lowercase) email, by JSON path
+#[derive(Default)]
+struct Uses {
+    /// The layer being checked, named in the paths recorded (see `check_layers`)
+    origin: Option<String>,
+    aliases: HashMap<String, (String, String)>,
+    emails: HashMap<String, String>,
+}
+
+impl Uses {
+    /// Record the aliases and the email of a remailer, pushing an error for each one used before.
+    /// The remailer of an `overlay` can be known, and keep its aliases.
+    fn check(
+        &mut self,
+        remailer: &Remailer,
+        path: &str,
+        overlay: bool,
+        errors: &mut Vec<ConfigError>,
+    ) {
+        let email = remailer.email.to_lowercase();
+        match self.emails.get(&email) {
+            Some(_) if overlay => {}
+            Some(first) => errors.push(ConfigError::DuplicateEmail {
+                path: format!("{}.email", path),
+                email: remailer.email.clone(),
+                first: first.clone(),
+            }),
+            None => {
+                let recorded = self.recorded(&format!("{}.email", path));
+                self.emails.insert(email.clone(), recorded);
+            }
+        }
+        for (position, alias) in remailer.name.iter().enumerate() {
+            let alias_path = format!("{}.name[{}]", path, position);
+            let first = match self.aliases.get(alias) {
+                Some((_, owner)) if overlay && *owner == email => continue,
+                Some((first, _)) => Some(first),
+                None => self
+                    .emails
+                    .get(&alias.to_lowercase())
+                    .filter(|_| alias.to_lowercase() != email),
+            };
+            match first {
+                Some(first) => errors.push(ConfigError::DuplicateAlias {
+                    path: alias_path,
+                    alias: alias.clone(),
+                    first: first.clone(),
+                }),
+                None => {
+                    let recorded = self.recorded(&alias_path);
+                    self.aliases
+                        .insert(alias.clone(), (recorded, email.clone()));
+                }
+            }
+        }
+    }
+
+    /// Return the path recorded for a use: in its layer, if any
+    fn recorded(&self, path: &str) -> String {
+        match &self.origin {
+            Some(origin) => format!("{} in {}", path, origin),
+            None => path.to_string(),
+        }
+    }
+}
+
+/// Check the type of a required field
+fn check_field(
+    errors: &mut Vec<ConfigError>,
+    value: Option<&Value>,
+    path: &str,
+    is_valid: fn(&Value) -> bool,
+    expected: &'static str,
+) {
+    match value {
+        None => errors.push(ConfigError::Missing {
+            path: path.to_string(),
+        }),
+        Some(value) if !is_valid(value) => errors.push(type_error(path, expected)),
+        Some(_) => {}
+    }
+}
+
+/// Return if the value is a list of strings
+fn is_string_list(value: &Value) -> bool {
+    value
+        .as_array()
+        .is_some_and(|list| list.iter().all(Value::is_string))
+}
+
+/// Return if the value is remailer statistics, with `latency` and `uptime` strings
+fn is_stats(value: &Value) -> bool {
+    ["latency", "uptime"]
+        .iter()
+        .all(|field| value.get(field).is_some_and(Value::is_string))
+}
+
+fn type_error(path: &str, expected: &'static str) -> ConfigError {
+    ConfigError::Type {
+        path: path.to_string(),
+        expected,
+    }
+}
+
+/// Make a map of name-to-remailer from a list of remailers
+pub fn remailer_map(remailers: Vec<Remailer>) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    if !remailers.iter().any(|remailer| remailer.enable) {
+        log::warn!("No enabled remailer in the config, no chain can be built!")
+    }
+    // For each remailers
+    for remailer in remailers {
+        // We check if it is enabled
+        if remailer.enable {
+            // For each alias and email of this remailer, we add it to the map
+            map.insert(remailer.email.clone(), remailer.email.clone());
+            for alias in remailer.name {
+                map.insert(alias, remailer.email.clone());
+            }
+        }
+    }
+    map
+}
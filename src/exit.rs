@@ -0,0 +1,84 @@
+use std::fmt;
+use std::io::Write;
+
+use failure::{Context, Error, Fallible, ResultExt};
+
+use crate::config::{ConfigError, KeyError};
+
+/// The exit code of a failure of no known kind
+pub const FAILURE: i32 = 1;
+
+/// The kind of a failure, giving the exit code of the process. An error is given its kind as
+/// context: `.context(ErrorKind::Config)`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    /// Invalid arguments: an unknown option, remailer or profile, or a chain that can't be built
+    Usage,
+    /// A remailer config or a settings file that can't be read, or is invalid or unauthenticated
+    Config,
+    /// Remailer keys that can't be imported, or are invalid
+    KeyImport,
+    /// A failure of the PGP backend
+    Backend,
+    /// The input can't be read, or the output written (a file, stdout or the delivery command)
+    Io,
+    /// Some copies failed, the others were encrypted or sent
+    Partial,
+}
+
+impl ErrorKind {
+    /// Return the exit code of the process failing with this kind of error
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Usage => 2,
+            ErrorKind::Config => 3,
+            ErrorKind::KeyImport => 4,
+            ErrorKind::Backend => 5,
+            ErrorKind::Io => 6,
+            ErrorKind::Partial => 7,
+        }
+    }
+
+    /// Return the kind of an error: the first one given by its chain of causes, a config or key
+    /// error being of its kind
+    pub fn of(err: &Error) -> Option<ErrorKind> {
+        err.iter_chain().find_map(|fail| {
+            if let Some(context) = fail.downcast_ref::<Context<ErrorKind>>() {
+                Some(*context.get_context())
+            } else if fail.downcast_ref::<ConfigError>().is_some() {
+                Some(ErrorKind::Config)
+            } else if fail.downcast_ref::<KeyError>().is_some() {
+                Some(ErrorKind::KeyImport)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorKind::Usage => "Invalid arguments",
+            ErrorKind::Config => "Invalid config",
+            ErrorKind::KeyImport => "Invalid remailer keys",
+            ErrorKind::Backend => "The PGP backend failed",
+            ErrorKind::Io => "Cannot read the input or write the output",
+            ErrorKind::Partial => "Some copies failed",
+        })
+    }
+}
+
+/// Return the exit code of the process failing with an error: the one of its kind, 1 without any
+pub fn exit_code(err: &Error) -> i32 {
+    ErrorKind::of(err).map_or(FAILURE, ErrorKind::exit_code)
+}
+
+/// Write results on an output, stdout for the commands: a failure, as stdout closed by `| head`,
+/// is an `Io` error
+pub fn write_out(out: &mut impl Write, text: fmt::Arguments<'_>) -> Fallible<()> {
+    out.write_fmt(text)
+        .context("Cannot write the output")
+        .context(ErrorKind::Io)?;
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use std::path::PathBuf;
+
+use clap::arg_enum;
+use failure::{err_msg, Fallible, ResultExt};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde_derive::Serialize;
+
+// Possible output formats
+arg_enum! {
+    #[non_exhaustive]
+    #[derive(PartialEq, Debug, Copy, Clone)]
+    pub enum OutputFormat {
+        Cypherpunk,
+        Mailto,
+        EML,
+    }
+}
+
+impl OutputFormat {
+    /// Get the specific extension for this particular format
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::EML => "eml",
+            _ => "txt",
+        }
+    }
+}
+
+/// An encrypted copy of a message, as listed by `--json`
+#[derive(Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct EncryptedCopy {
+    /// The number of the copy, from 1
+    pub copy: usize,
+    /// The emails of the remailers of its chain
+    pub chain: Vec<String>,
+    /// The email of the first remailer, where the copy is sent
+    pub entry: String,
+    /// The format of the payload, in lower case
+    pub format: String,
+    /// The formatted copy
+    pub payload: String,
+    /// The file where the copy is written, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+impl EncryptedCopy {
+    /// Make the copy number `index + 1`, encrypted for a chain and formatted
+    pub fn new(index: usize, chain: Vec<String>, format: OutputFormat, payload: String) -> Self {
+        EncryptedCopy {
+            copy: index + 1,
+            entry: chain.first().cloned().unwrap_or_default(),
+            chain,
+            format: format.to_string().to_lowercase(),
+            payload,
+            file: None,
+        }
+    }
+}
+
+/// Format a message for a particular OutputFormat, can fail.
+pub fn format_msg(format: OutputFormat, msg: String) -> Fallible<String> {
+    match format {
+        OutputFormat::Cypherpunk => Ok(msg),
+        OutputFormat::Mailto => {
+            Ok(format_mailto(msg).context("Failed to format the message in mailto URL")?)
+        }
+        OutputFormat::EML => {
+            Ok(format_eml(msg).context("Failed to format the message in a EML email")?)
+        }
+        // In the future case of unimplemented format...
+        #[allow(unreachable_patterns)]
+        other => Err(err_msg(format!("Format {:?} not yet implemented!", other))),
+    }
+}
+
+/// Format a given message to an EML-formatted email
+fn format_eml(message: String) -> Fallible<String> {
+    // Get address and message body
+    let (addr, message) = format_helper(message)?;
+
+    // Format it and return!
+    Ok(format!(
+        "MIME-Version: 1.0\n\
+    Content-Type: text/plain; charset=utf-8\n\
+    To: {}\n\
+    \n\
+    {}",
+        addr, message
+    ))
+}
+
+/// Format a given message to an mailto URL
+fn format_mailto(message: String) -> Fallible<String> {
+    // Get address and message body
+    let (addr, message) = format_helper(message)?;
+
+    // Encode body into utf-8 percent encode (to avoid special URL token)
+    let body = utf8_percent_encode(message.as_str(), NON_ALPHANUMERIC).to_string();
+    // Make it URL and return!
+    Ok(format!("mailto:{}?body={}", addr, body))
+}
+
+/// Get from a given message, the message's recipient and the message's body
+fn format_helper(mut message: String) -> Fallible<(String, String)> {
+    // Find the `Anon-To` header
+    let addr_start = message
+        .find("Anon-To: ")
+        .ok_or_else(|| err_msg("Invalid Cypherpunk message (Anon-To header missing)"))?; // Find the address start
+    message.drain(..addr_start + 9); // Drop all chars before email address
+    let addr_end = message.find('\n').ok_or_else(|| {
+        err_msg("Invalid Cypherpunk message (Anon-To header is the only line in message)")
+    })?; // Find the address end
+    let addr: String = message.drain(..addr_end).collect(); // Save the email address in the var, and drop all chars before message
+                                                            // Look for a body (always separated by two line return to the headers)
+    let body_start = message
+        .find("\n\n")
+        .ok_or_else(|| err_msg("Invalid Cypherpunk message (Body not found)"))?
+        + 2;
+    message.drain(..body_start); // Drop all the char before the body
+                                 // Return the recipient address and the remaining message
+    Ok((addr, message))
+}
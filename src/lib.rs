@@ -1,7 +1,30 @@
 use std::io::{Cursor, Read, Write};
 
-use failure::Fallible;
 use failure::ResultExt;
+use failure::{err_msg, Fallible};
+
+use crate::message::{Header, Message};
+use crate::plan::Hop;
+
+pub mod chain;
+pub mod config;
+pub mod exit;
+pub mod format;
+pub mod list;
+pub mod logger;
+pub mod message;
+pub mod migrate;
+pub mod pgp;
+pub mod plan;
+pub mod pubring;
+pub mod refresh;
+pub mod remailer;
+pub mod selftest;
+pub mod server;
+pub mod settings;
+#[cfg(any(test, feature = "test-backend"))]
+pub mod testnet;
+pub mod update;
 
 /// Representation of a capable Cypherpunk core
 pub trait Cypherpunk {
@@ -14,6 +37,9 @@ pub trait Cypherpunk {
         addheaders: &[String],
         message: Vec<u8>,
     ) -> Fallible<Vec<u8>>;
+    /// Encrypt the given message for the given route, from the first hop to the last one,
+    /// each hop with its own headers
+    fn encrypt_route(&self, route: &[Hop], message: Vec<u8>) -> Fallible<Vec<u8>>;
 }
 
 /// Representation of a PGP back-end usable by a Cypherpunk-capable core
@@ -27,6 +53,18 @@ pub trait PGPBackend {
         output: &mut dyn Write,
         recipients: Vec<String>,
     ) -> Fallible<()>;
+    /// Generate a new key pair in its keyring for the given user ID, return the public key
+    fn generate_key(&self, _user_id: &str) -> Fallible<Vec<u8>> {
+        Err(err_msg("This PGP backend can't generate keys"))
+    }
+    /// Decrypt an input to an output with the secret keys of its keyring
+    fn decrypt(&self, _input: &mut dyn Read, _output: &mut dyn Write) -> Fallible<()> {
+        Err(err_msg("This PGP backend can't decrypt messages"))
+    }
+    /// Verify a detached signature of some data, made by one of the keys of its keyring
+    fn verify(&self, _data: &[u8], _signature: &[u8]) -> Fallible<()> {
+        Err(err_msg("This PGP backend can't verify signatures"))
+    }
 }
 
 /// The actual Cypherpunk core associated with a PGPBackend
@@ -62,36 +100,47 @@ impl<P: PGPBackend> Cypherpunk for CypherpunkCore<P> {
         addheaders: &[String],
         message: Vec<u8>,
     ) -> Fallible<Vec<u8>> {
-        // Encrypt the message throught the remailer chain
-        chain.iter().fold(Ok(message), |input, remailer| {
+        // The chain starts with the farther remailer, the route with the first one
+        let route: Vec<Hop> = chain
+            .iter()
+            .rev()
+            .map(|remailer| Hop {
+                email: remailer.clone(),
+                fingerprint: None,
+                headers: addheaders.to_vec(),
+                delay: None,
+            })
+            .collect();
+        self.encrypt_route(&route, message)
+    }
+
+    fn encrypt_route(&self, route: &[Hop], message: Vec<u8>) -> Fallible<Vec<u8>> {
+        // Encrypt the message throught the remailer route, from the farther remailer
+        route.iter().rev().try_fold(message, |input, hop| {
             // Pepare to encryption
-            let mut readin = Cursor::new(input?);
+            let mut readin = Cursor::new(input);
             let mut writeout: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-            let recipients = vec![remailer.clone()];
-            let headers = [addheaders, &[format!("Anon-To: {}", remailer)]].concat().join("\n");
-            // Make the wrapper message to which add the encrypted body
-            let message = format!(
-                "::\n{}\n\n::\nEncrypted: PGP\n\n",
-                headers
-            );
+            let recipients = vec![hop.recipient()];
+            let mut headers = hop
+                .all_headers()
+                .iter()
+                .map(|header| Header::parse(header))
+                .collect::<Fallible<Vec<Header>>>()?;
+            headers.push(Header::new("Anon-To", &hop.email));
 
             // Encrypt the message for the remailer
             self.pgp
                 .encrypt(&mut readin, &mut writeout, recipients)
                 .context("Encryption failed!")?;
+            let encrypted = String::from_utf8(writeout.into_inner())
+                .context("The encrypted message is not ASCII-armored")?;
 
-            // Format the final message in Cypherpunk format
-            let mut output: Vec<u8> = Vec::new();
-            // Write the wrapper message
-            output
-                .write_all(message.as_bytes())
-                .context("Cannot write the message wrapper to the output")?;
-            // Add the encapsulated and now encrypted body
-            output
-                .write_all(writeout.into_inner().as_slice())
-                .context("Cannot write the encapsulated message in the wrapper message")?;
-            Ok(output)
+            // Format the final message in Cypherpunk format: the wrapper message with the
+            // encapsulated and now encrypted body
+            let body = Message::new(vec![Header::new("Encrypted", "PGP")], encrypted);
+            Ok(Message::new(headers, body.to_string())
+                .to_string()
+                .into_bytes())
         })
     }
 }
-
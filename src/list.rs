@@ -0,0 +1,203 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::arg_enum;
+use failure::Fallible;
+use serde_derive::Serialize;
+
+use crate::config::Remailer;
+use crate::pgp::packet;
+use crate::refresh::civil_date;
+
+// Possible formats of the remailer list
+arg_enum! {
+    #[derive(PartialEq, Debug, Copy, Clone)]
+    pub enum ListFormat {
+        Table,
+        JSON,
+        CSV,
+    }
+}
+
+/// The status of the key of a remailer
+#[derive(Serialize, Eq, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStatus {
+    Valid,
+    Expired,
+    Invalid,
+}
+
+/// A remailer, as listed: what the config tells about it, and about its key
+#[derive(Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct RemailerRow {
+    pub aliases: Vec<String>,
+    pub email: String,
+    pub enabled: bool,
+    pub capabilities: Vec<String>,
+    pub key: KeyStatus,
+    /// Why the key is invalid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_error: Option<String>,
+    pub fingerprint: Option<String>,
+    /// The expiry date of the key, as `YYYY-MM-DD`; none if it never expires
+    pub key_expiry: Option<String>,
+    pub latency: Option<String>,
+    pub uptime: Option<String>,
+}
+
+impl RemailerRow {
+    /// Make the row of a remailer, its key being expired or not at the given time (seconds
+    /// since the epoch)
+    pub fn new(remailer: &Remailer, now: u64) -> Self {
+        let key = remailer
+            .as_key()
+            .map_err(failure::Error::from)
+            .and_then(|key| Ok((packet::fingerprint(&key)?, packet::expiry(&key)?)));
+        let (key, key_error, fingerprint, expiry) = match key {
+            Ok((fingerprint, expiry)) => (
+                match expiry {
+                    Some(expiry) if expiry <= now => KeyStatus::Expired,
+                    _ => KeyStatus::Valid,
+                },
+                None,
+                Some(fingerprint),
+                expiry,
+            ),
+            Err(err) => (KeyStatus::Invalid, Some(err.to_string()), None, None),
+        };
+        RemailerRow {
+            aliases: remailer.name.clone(),
+            email: remailer.email.clone(),
+            enabled: remailer.enable,
+            capabilities: remailer.capabilities.clone(),
+            key,
+            key_error,
+            fingerprint,
+            key_expiry: expiry.map(iso_date),
+            latency: remailer.stats.as_ref().map(|stats| stats.latency.clone()),
+            uptime: remailer.stats.as_ref().map(|stats| stats.uptime.clone()),
+        }
+    }
+
+    /// Return the fields of the row, as text, the missing ones being `missing`
+    fn fields(&self, missing: &str) -> Vec<String> {
+        let or_missing =
+            |field: &Option<String>| field.clone().unwrap_or_else(|| missing.to_string());
+        vec![
+            self.aliases.join(" "),
+            self.email.clone(),
+            if self.enabled { "yes" } else { "no" }.to_string(),
+            if self.capabilities.is_empty() {
+                missing.to_string()
+            } else {
+                self.capabilities.join(" ")
+            },
+            match self.key {
+                KeyStatus::Valid => "valid",
+                KeyStatus::Expired => "expired",
+                KeyStatus::Invalid => "invalid",
+            }
+            .to_string(),
+            or_missing(&self.fingerprint),
+            match (&self.key_expiry, self.key) {
+                (None, KeyStatus::Invalid) => missing.to_string(),
+                (None, _) => "never".to_string(),
+                (Some(expiry), _) => expiry.clone(),
+            },
+            or_missing(&self.latency),
+            or_missing(&self.uptime),
+        ]
+    }
+}
+
+/// The column names of the rows
+const COLUMNS: &[&str] = &[
+    "ALIASES",
+    "EMAIL",
+    "ENABLED",
+    "CAPABILITIES",
+    "KEY",
+    "FINGERPRINT",
+    "EXPIRES",
+    "LATENCY",
+    "UPTIME",
+];
+
+/// Make the rows of the remailers, now
+pub fn remailer_rows(remailers: &[&Remailer]) -> Vec<RemailerRow> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    remailers
+        .iter()
+        .map(|remailer| RemailerRow::new(remailer, now))
+        .collect()
+}
+
+/// Format the rows: as a table with aligned columns, as a JSON array, or as CSV with a header
+pub fn format_rows(rows: &[RemailerRow], format: ListFormat) -> Fallible<String> {
+    Ok(match format {
+        ListFormat::Table => to_table(rows),
+        ListFormat::JSON => serde_json::to_string_pretty(rows)? + "\n",
+        ListFormat::CSV => to_csv(rows),
+    })
+}
+
+/// Format the rows as a table, each column being as wide as its widest field
+fn to_table(rows: &[RemailerRow]) -> String {
+    let lines: Vec<Vec<String>> =
+        std::iter::once(COLUMNS.iter().map(|column| column.to_string()).collect())
+            .chain(rows.iter().map(|row| row.fields("-")))
+            .collect();
+    let widths: Vec<usize> = (0..COLUMNS.len())
+        .map(|column| {
+            lines
+                .iter()
+                .map(|line| line[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut table = String::new();
+    for line in lines {
+        let fields: Vec<String> = line
+            .iter()
+            .zip(&widths)
+            .map(|(field, &width)| format!("{:<width$}", field, width = width))
+            .collect();
+        table.push_str(fields.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// Format the rows as CSV (RFC 4180), the lists being space-separated and the missing fields
+/// empty
+fn to_csv(rows: &[RemailerRow]) -> String {
+    let quote = |field: &str| {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    };
+    let mut csv = COLUMNS
+        .iter()
+        .map(|column| column.to_lowercase())
+        .collect::<Vec<String>>()
+        .join(",");
+    csv.push_str("\r\n");
+    for row in rows {
+        let fields: Vec<String> = row.fields("").iter().map(|field| quote(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Format a time (seconds since the epoch) as an ISO date: `YYYY-MM-DD` (UTC)
+pub fn iso_date(seconds: u64) -> String {
+    let (year, month, day) = civil_date(seconds / 86400);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
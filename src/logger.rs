@@ -0,0 +1,44 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// The logger of the diagnostics: everything goes to stderr, so that stdout only has the results
+/// (the encrypted messages, the lists, the reports). The warnings and errors are prefixed.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Error => eprintln!("Error: {}", record.args()),
+            Level::Warn => eprintln!("Warning: {}", record.args()),
+            Level::Info => eprintln!("{}", record.args()),
+            level => eprintln!("[{} {}] {}", level, record.target(), record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Return the level of the diagnostics for the `-q` and `-v` flags: the errors only when quiet,
+/// the informations by default, then the debug and the trace ones for each `-v`
+pub fn level(quiet: bool, verbose: u8) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+/// Send the diagnostics of this level (and the more important ones) to stderr
+pub fn init(level: LevelFilter) {
+    // Only fails if a logger is already set, which keeps it
+    if log::set_logger(&StderrLogger).is_ok() {
+        log::set_max_level(level);
+    }
+}
@@ -1,77 +1,163 @@
 use std::collections::HashMap;
+use std::ffi::OsString;
 use std::fs::{create_dir_all, File};
 use std::io::{stdin, Read, Write};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::thread::sleep;
+use std::time::SystemTime;
 
-use clap::arg_enum;
 use failure::Error as FError;
-use failure::{err_msg, Fallible, ResultExt};
-use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
-use rand::prelude::ThreadRng;
-use rand::seq::IteratorRandom;
-use rand::thread_rng;
-use serde_derive::Deserialize;
-use structopt::StructOpt;
-
-use crate::lib::{Cypherpunk, CypherpunkCore, PGPBackend};
-#[cfg(feature = "back-gpg")]
-use crate::pgp::gpg::GPGBackend;
+use failure::{err_msg, Context, Fail, Fallible, ResultExt};
+use log::{debug, error, info, warn};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use structopt::{clap, StructOpt};
+use tempfile::tempdir;
 
-mod lib;
-mod pgp;
+use cypherpunk_cli::chain::{expand_chain, expand_named_chains, make_chain, select_remailers};
+use cypherpunk_cli::config::{
+    check_layers, config_dir, config_dirs, config_file, config_paths, load_config,
+    load_layered_config, load_layers, read_config, remailer_map, set_enable, signer_key_paths,
+    verify_layers, write_config, ConfigError, ConfigFormat, ConfigLayer, KeyError, Remailer,
+    RemailerConfig,
+};
+use cypherpunk_cli::exit::{exit_code, write_out, ErrorKind};
+use cypherpunk_cli::format::{format_msg, EncryptedCopy, OutputFormat};
+use cypherpunk_cli::list::{format_rows, remailer_rows, ListFormat};
+use cypherpunk_cli::logger;
+use cypherpunk_cli::migrate::{migrate, schema_version, CURRENT_SCHEMA};
+#[cfg(feature = "back-gpg")]
+use cypherpunk_cli::pgp::gpg::{self, GPGBackend};
+use cypherpunk_cli::pgp::{armor, packet};
+use cypherpunk_cli::plan::{Plan, Route};
+use cypherpunk_cli::pubring::{update_keys, update_remailers, Pubring};
+use cypherpunk_cli::refresh::{read_mailbox, refresh, requests, to_mbox};
+use cypherpunk_cli::remailer::{peel, read_envelope, Delivery};
+use cypherpunk_cli::selftest::{self as backend_selftest, Stage, STAGES};
+use cypherpunk_cli::server::{maildir_done, maildir_new, run_delivery, Server, MAILDIR_POLL};
+use cypherpunk_cli::settings::{load_settings, settings_paths, Profile, DEFAULT_PROFILE};
+use cypherpunk_cli::update::{apply, diff, MergePolicy};
+use cypherpunk_cli::{Cypherpunk, CypherpunkCore, PGPBackend};
+use serde_json::Value;
 
-// Possible output formats
-arg_enum! {
-    #[non_exhaustive]
-    #[derive(PartialEq, Debug, Copy, Clone)]
-    pub enum OutputFormat {
-        Cypherpunk,
-        Mailto,
-        EML,
-    }
+/// Print results on stdout like `print!`, failing with an `Io` error instead of panicking when
+/// stdout is closed (as by `| head`)
+macro_rules! out {
+    ($($arg:tt)*) => {
+        write_out(&mut std::io::stdout().lock(), format_args!($($arg)*))?
+    };
 }
 
-impl OutputFormat {
-    /// Get the specific extension for this particular format
-    fn extension(self) -> &'static str {
-        match self {
-            OutputFormat::EML => "eml",
-            _ => "txt",
-        }
-    }
+/// Print a line of results on stdout like `println!`, see `out!`
+macro_rules! outln {
+    () => {
+        out!("\n")
+    };
+    ($($arg:tt)*) => {
+        out!("{}\n", format_args!($($arg)*))
+    };
 }
 
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "cypherpunk",
     author,
-    about = "CLI tool to encrypt your messages between different remailers easily"
+    about = "CLI tool to encrypt your messages between different remailers easily",
+    after_help = "Without a subcommand, the options are the ones of `encrypt`: \
+                  `cypherpunk --chain dizum` is `cypherpunk encrypt --chain dizum`."
 )]
 struct Opt {
+    #[structopt(flatten)]
+    global: GlobalOpt,
+
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+// The options of every command
+#[derive(Debug, StructOpt)]
+struct GlobalOpt {
+    /// The path to the remailer config, on top of the configs found in `/etc/cypherpunk/`,
+    /// `$XDG_CONFIG_HOME/cypherpunk/` and the working directory (all named `remailers.json`, or
+    /// `remailers.toml`, `remailers.yaml`). A config is read as JSON, TOML or YAML, by its
+    /// extension or else by its content.
+    ///
+    /// A config with a `version` is a complete remailer list replacing the ones before it;
+    /// without, it's an overlay: its remailers, matched by email, are disabled or get new aliases.
+    /// The remailer list embedded in the binary is used when no config is found.
+    #[structopt(long, global = true, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Key of a trusted list signer, on top of the keys in the `signers` directory next to the
+    /// system and user configs. The configs giving remailer keys must have a detached signature
+    /// (`remailers.json.sig` or `.asc`) made by one of them.
+    #[structopt(long = "signer", global = true, parse(from_os_str))]
+    signers: Vec<PathBuf>,
+
+    /// Load the configs without checking their signatures.
+    ///
+    /// Warning: a tampered config can give the keys of anybody for the remailers!
+    #[structopt(long, global = true)]
+    insecure_config: bool,
+
+    /// Only print the errors on stderr, and make the PGP backend quiet.
+    #[structopt(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print more diagnostics on stderr: the debug ones with `-v`, and the trace ones (as the
+    /// GnuPG commands) with `-vv`. The results, as the encrypted messages, are the only output
+    /// on stdout.
+    #[structopt(short, long, global = true, parse(from_occurrences))]
+    verbose: u8,
+}
+
+// The options choosing the routes of the message copies, for `encrypt` and `send` (not doc
+// comments, which would replace the about of the commands flattening them)
+#[derive(Debug, Clone, StructOpt)]
+struct RouteOpt {
     /// Messsage input file, stdin if not present; the message must be readable by the last Cypherpunk
     /// remailer in the chain.
     #[structopt(short, long, parse(from_os_str))]
     input: Option<PathBuf>,
 
-    /// Output dir, stdout if not present; all the encrypted message for remailer will be there.
-    #[structopt(short, long, parse(from_os_str))]
-    output: Option<PathBuf>,
-
     /// Number of redundancy message to encrypted
     ///
     /// Because the Cypherpunk remailers can forget messages, it's a good idea to send several messages
     /// to different remailers to avoid the loss of the message.
     /// Tips: If you use a "*" for remailer it will be randomly choose for each redundancy message.
-    #[structopt(short, long, default_value = "1")]
-    redundancy: u8,
+    /// [default: 1]
+    #[structopt(short, long)]
+    redundancy: Option<u8>,
 
-    /// The remailer chain through which your message will pass. [required]
+    /// The remailer chain through which your message will pass. [required without --hops]
     ///
     /// Tips: You can use a joker "*" to randomly choose one remailer in the config. It will change
-    /// with each redundant message.
+    /// with each redundant message. Like Mixmaster, a comma-separated list is accepted too:
+    /// `--chain "*,*,dizum"`.
+    /// A joker can be limited to a domain, as `*@eu`, and the chain of a profile can be used as
+    /// `@name` (see `--profile`).
     #[structopt(short, long)]
     chain: Vec<String>,
 
+    /// Number of hops of the chain; missing hops are randomly chosen and added before the given chain.
+    ///
+    /// Examples:
+    /// `--hops 3` for three random remailers
+    /// `--chain dizum --hops 3` for two random remailers followed by dizum
+    #[structopt(long)]
+    hops: Option<usize>,
+
+    /// Replace the unknown or disabled remailers of the chain by random ones instead of failing.
+    ///
+    /// Warning: the chain may not go through the remailers you gave!
+    #[structopt(long, overrides_with = "no-allow-missing")]
+    allow_missing: bool,
+
+    /// Fail on the unknown or disabled remailers of the chain, even if the profile ignores them.
+    #[structopt(long, overrides_with = "allow-missing")]
+    no_allow_missing: bool,
+
     /// Remailer headers to add for each remailer message. Only one key-value per string.
     ///
     /// This can be useful to add `Inflate` header to each message.
@@ -82,354 +168,1771 @@ struct Opt {
     #[structopt(short = "H", long = "header")]
     headers: Vec<String>,
 
-    /// The output message format.
-    #[structopt(short, long, possible_values = & OutputFormat::variants(), case_insensitive = true, default_value = "cypherpunk")]
-    format: OutputFormat,
+    /// The profile of the settings giving the options not given here: its chain and hops
+    /// (without `--chain` and `--hops`), redundancy, headers, format and allow_missing. The
+    /// `default` profile is used if any.
+    ///
+    /// The profiles are read from the `settings.toml` (or `.json`, `.yaml`) files of the config
+    /// directories (see `--config`), as `[profile.safe]`.
+    #[structopt(short, long)]
+    profile: Option<String>,
+
+    /// Encrypt the message using exactly the routing plan of this JSON file, made with
+    /// `--plan-out`; the chain options are ignored, and the settings are not read.
+    #[structopt(long, parse(from_os_str), conflicts_with = "profile")]
+    plan_in: Option<PathBuf>,
 
-    /// The path to the remailer config, useful if you have install this tool.
-    #[structopt(long, default_value = "./remailers.json")]
-    config: PathBuf,
+    /// Seed of the random remailer selection, to reproduce the same chains. For debugging only!
+    ///
+    /// Warning: anybody knowing the seed can guess the chosen remailers.
+    #[structopt(long)]
+    seed: Option<u64>,
+}
 
-    /// The quiet flag to make the PGP backend quiet and soon more...
-    #[structopt(short, long)]
-    quiet: bool,
+// The options of `encrypt`
+#[derive(Debug, StructOpt)]
+struct EncryptOpt {
+    #[structopt(flatten)]
+    route: RouteOpt,
+
+    /// Output dir, stdout if not present; all the encrypted message for remailer will be there.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// The output message format. [default: cypherpunk]
+    #[structopt(short, long, possible_values = & OutputFormat::variants(), case_insensitive = true)]
+    format: Option<OutputFormat>,
+
+    /// Write the routing plan (remailers, keys, headers and delays of each redundancy message)
+    /// into this JSON file and exit without encrypting, to review it before sending.
+    #[structopt(long, parse(from_os_str), conflicts_with = "plan-in")]
+    plan_out: Option<PathBuf>,
+
+    /// Show what each remailer of each route will read, without encrypting anything.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Write a single JSON document on stdout instead of the messages: `{"copies": [...]}`,
+    /// each copy with its `chain`, its `entry` remailer, its `format` and its formatted
+    /// `payload` (and its `file` with `--output`).
+    #[structopt(long, conflicts_with = "dry-run")]
+    json: bool,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Encrypt a message through a chain of remailers, the default command: its options can be
+    /// given without `encrypt`.
+    Encrypt(EncryptOpt),
+
+    /// Encrypt a message like `encrypt`, then hand each copy to a delivery command which sends
+    /// it to the first remailer of its chain.
+    Send {
+        #[structopt(flatten)]
+        route: RouteOpt,
+
+        /// Delivery command, reading each email to send on its standard input.
+        #[structopt(long, default_value = "sendmail -t -oi")]
+        deliver: String,
+
+        /// Write a single JSON document on stdout listing the copies sent, see `encrypt --json`.
+        #[structopt(long)]
+        json: bool,
+    },
+
+    /// Show the remailers of the config (see `--config`), and enable or disable them.
+    Remailers(RemailersCommand),
+
+    /// List, verify and import the keys of the remailers.
+    Keys(KeysCommand),
+
+    /// Peel an encrypted message the way the remailers would, with their secret keys, to check
+    /// its path and the finally delivered message.
+    Peel {
+        /// Encrypted message file (Cypherpunk, EML or mailto format), stdin if not present.
+        #[structopt(short, long, parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// Secret keys of the remailers (test keys only!), one file per remailer.
+        #[structopt(short, long = "key", parse(from_os_str), required = true)]
+        keys: Vec<PathBuf>,
+    },
+
+    /// Act as a Type-I remailer: decrypt the incoming emails with the remailer's secret key,
+    /// follow their `::` headers and hand the results to a delivery command.
+    Serve {
+        /// Maildir of the incoming emails, processed from `new` and moved to `cur` once their
+        /// result is delivered; one email from stdin if not present. The Maildir is read again
+        /// while delayed emails wait, the command ends when none is left.
+        #[structopt(long, parse(from_os_str))]
+        maildir: Option<PathBuf>,
+
+        /// Secret key of the remailer, imported in the GnuPG home directory (a temporary one
+        /// without `--homedir`).
+        #[structopt(short, long, parse(from_os_str), required_unless = "homedir")]
+        key: Option<PathBuf>,
+
+        /// GnuPG home directory holding the secret key of the remailer.
+        #[structopt(long, parse(from_os_str))]
+        homedir: Option<PathBuf>,
+
+        /// Delivery command, reading each email to send on its standard input.
+        #[structopt(long, default_value = "sendmail -t -oi")]
+        deliver: String,
+    },
+
+    /// Check the PGP backend end to end with ephemeral keys, before trusting it with real
+    /// messages: encrypt a known message through a fake 3-hop network and decrypt each layer.
+    Selftest,
+
+    /// Diagnose the common problems: GnuPG missing or too old, unwritable temporary directory,
+    /// missing config (see `--config`), undecodable keys or keys not matching their remailer.
+    Doctor,
+
+    /// Manage the remailer config (see `--config`).
+    Config(ConfigCommand),
+}
+
+#[derive(Debug, StructOpt)]
+enum RemailersCommand {
+    /// List the remailers of the config: their aliases, email, capabilities, key (fingerprint
+    /// and expiry) and statistics, and if they are enabled. These are the names accepted in the
+    /// chains.
+    List {
+        /// Only list the remailers matching any of these selectors, with the syntax of the chain
+        /// (see `encrypt --chain`): `*` for the enabled remailers, `*@domain` for the enabled
+        /// ones of a domain, `@name` for the chain of a profile, or the alias or the email of a
+        /// remailer. All the remailers if not present.
+        selectors: Vec<String>,
+
+        /// The output format of the list.
+        #[structopt(short, long, possible_values = &ListFormat::variants(), case_insensitive = true, default_value = "table")]
+        format: ListFormat,
+    },
+
+    /// Show everything known about a remailer: its aliases, capabilities, key and statistics.
+    Show {
+        /// An alias or the email of the remailer
+        name: String,
+    },
+
+    /// Enable remailers, in the user config (an overlay, see `--config`) or the given one.
+    Enable {
+        /// The aliases or the emails of the remailers
+        #[structopt(required = true)]
+        names: Vec<String>,
+
+        /// The config to write into, created if missing; the user config if not present.
+        #[structopt(long, parse(from_os_str))]
+        into: Option<PathBuf>,
+    },
+
+    /// Disable remailers, in the user config (an overlay, see `--config`) or the given one.
+    Disable {
+        /// The aliases or the emails of the remailers
+        #[structopt(required = true)]
+        names: Vec<String>,
+
+        /// The config to write into, created if missing; the user config if not present.
+        #[structopt(long, parse(from_os_str))]
+        into: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum KeysCommand {
+    /// List the key of each remailer: its fingerprint, pinned or not, and its user IDs.
+    List,
+
+    /// Verify the key of each enabled remailer: it must decode, match its pinned fingerprint, and
+    /// have the email of the remailer as user ID.
+    Verify {
+        /// Verify the keys of the disabled remailers too.
+        #[structopt(long)]
+        all: bool,
+    },
+
+    /// Import remailer keys (armored or binary key files) into the local list: each key replaces,
+    /// and pins, the key of the remailer of its user ID. The changes are reviewed like with
+    /// `config merge`.
+    Import {
+        /// The key files
+        #[structopt(parse(from_os_str), required = true)]
+        files: Vec<PathBuf>,
+
+        /// The local remailer list to update, a `.bak` copy of it is kept. The last complete
+        /// list of the config layers if not present.
+        #[structopt(long, parse(from_os_str))]
+        into: Option<PathBuf>,
+
+        /// How to apply the changes, see `config merge`.
+        #[structopt(long, possible_values = &MergePolicy::variants(), case_insensitive = true, default_value = "ask")]
+        policy: MergePolicy,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum ConfigCommand {
+    /// Check the config layers and report every problem with its file and JSON path: wrong
+    /// types, missing fields, empty name lists, duplicate aliases or emails, and undecodable
+    /// keys.
+    Check,
+
+    /// Upgrade a config file to the current schema version, keeping a `.bak` copy of it.
+    Migrate {
+        /// The config file to upgrade
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+
+    /// Compare a new remailer list with the local one: report the added and removed remailers,
+    /// the changed keys (with their fingerprints) and the changed emails, then apply the
+    /// accepted changes. The local `enable` and aliases of each remailer are kept.
+    Merge {
+        /// The new remailer list, which must be signed like the configs (see `--signer`)
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+
+        /// The local remailer list to update, a `.bak` copy of it is kept. The last complete
+        /// list of the config layers if not present.
+        #[structopt(long, parse(from_os_str))]
+        into: Option<PathBuf>,
+
+        /// How to apply the changes: ask for each one, apply all of them, all of them but the
+        /// key and email changes and the added remailers (safe), or only report them.
+        #[structopt(long, possible_values = &MergePolicy::variants(), case_insensitive = true, default_value = "ask")]
+        policy: MergePolicy,
+    },
+
+    /// Import the remailers of a pinger's files: the keys of a `pubring.asc`, the
+    /// `$remailer{"name"} = "<email> capabilities"` lines, and the type-II remailers of a
+    /// `pubring.mix` or a `type2.list` (for their names). The remailers are printed as a new
+    /// list, or merged into a local one like with `config merge`.
+    ImportPubring {
+        /// The pinger's files
+        #[structopt(parse(from_os_str), required = true)]
+        files: Vec<PathBuf>,
+
+        /// The local remailer list to update, a `.bak` copy of it is kept; created if missing.
+        #[structopt(long, parse(from_os_str))]
+        into: Option<PathBuf>,
+
+        /// How to apply the changes to the local list, see `config merge`.
+        #[structopt(long, possible_values = &MergePolicy::variants(), case_insensitive = true, default_value = "ask")]
+        policy: MergePolicy,
+    },
+
+    /// Refresh the remailers with the replies to the `remailer-key` and `remailer-conf` requests
+    /// (see `config request`) found in a mailbox: their new keys, capabilities and statistics are
+    /// proposed as changes to the local list, like with `config merge`. The sender of a reply
+    /// isn't authenticated: a new key is always asked for, even with `--policy all`.
+    Refresh {
+        /// The mailbox of the replies: a Maildir, or an mbox file
+        #[structopt(parse(from_os_str))]
+        mailbox: PathBuf,
+
+        /// The local remailer list to update, a `.bak` copy of it is kept. The last complete
+        /// list of the config layers if not present.
+        #[structopt(long, parse(from_os_str))]
+        into: Option<PathBuf>,
+
+        /// How to apply the changes, see `config merge`.
+        #[structopt(long, possible_values = &MergePolicy::variants(), case_insensitive = true, default_value = "ask")]
+        policy: MergePolicy,
+    },
+
+    /// Write the `remailer-key` and `remailer-conf` requests to the enabled remailers, to send
+    /// them with any mail client.
+    Request {
+        /// The sender address of the requests, where the replies go
+        #[structopt(long)]
+        from: Option<String>,
+
+        /// The Maildir (an existing directory) or the mbox file of the requests, stdout as an
+        /// mbox if not present.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Request the disabled remailers too.
+        #[structopt(long)]
+        all: bool,
+    },
 }
 
 fn main() {
-    // Get the CLI args
-    let opts: Opt = Opt::from_args();
+    // Get the CLI args, the help and the version are not errors
+    let opts = parse_args(std::env::args_os().collect()).unwrap_or_else(|err| {
+        if err.use_stderr() {
+            eprintln!("{}", err.message);
+            process::exit(ErrorKind::Usage.exit_code());
+        }
+        err.exit()
+    });
+    logger::init(logger::level(opts.global.quiet, opts.global.verbose));
 
-    // To init a PGP Back, here for the back-gpg one.
-    #[cfg(feature = "back-gpg")]
-    fn init_pgp_back(quiet: bool) -> impl PGPBackend {
-        GPGBackend::new(None, quiet)
+    if let Err(err) = run_command(&opts.cmd, &opts.global) {
+        let code = exit_code(&err);
+        print_errors(err);
+        process::exit(code);
     }
+}
 
-    println!("Hello!");
-    println!("Config loading...");
+/// Parse the CLI args. Without a subcommand, they are the ones of `encrypt`; the options of
+/// `encrypt` are refused before another subcommand.
+fn parse_args(mut args: Vec<OsString>) -> Result<Opt, clap::Error> {
+    match Opt::clap().get_matches_from_safe(&args) {
+        Ok(matches) => return Ok(Opt::from_clap(&matches)),
+        Err(err) => match err.kind {
+            clap::ErrorKind::UnknownArgument
+            | clap::ErrorKind::MissingSubcommand
+            | clap::ErrorKind::MissingArgumentOrSubcommand => {}
+            _ => return Err(err),
+        },
+    }
+    args.insert(args.len().min(1), OsString::from("encrypt"));
+    let matches = Opt::clap().get_matches_from_safe(&args)?;
+    Ok(Opt::from_clap(&matches))
+}
 
-    // Load config (from path arg) and run all
-    load_config(&opts.config)
-        .and_then(|config| {
-            // Init a random thread and the remailer map from config
-            let mut rng = thread_rng();
-            let remmap = remailer_map(config.remailers.clone());
+/// Init a PGP backend, here the back-gpg one
+#[cfg(feature = "back-gpg")]
+fn init_pgp_back(quiet: bool) -> impl PGPBackend {
+    GPGBackend::new(None, quiet)
+}
 
-            // Init infra (the PGP backend)
-            let pgp_back = init_pgp_back(opts.quiet);
-            // Init the domain (the CypherpunkCore)
-            let core = CypherpunkCore::new(pgp_back);
-
-            // Import remailers' key
-            println!("Importing remailers' key...");
-            import_keys(&core, &config.remailers)
-                .context("Importation of remailers' key failed")?;
-
-            // Preparing the mail encrypting
-            // Select number of redundancy messages
-            let red = 0..opts.redundancy;
-
-            // Retrieve the message to send
-            let mut message: Vec<u8> = Vec::new();
-            match &opts.input {
-                // from path, if given
-                Some(path) => {
-                    println!("Retrieving message from file...");
-                    let mut file =
-                        File::open(&path).context(format!("File {:?} cannot be open!", &path))?;
-                    file.read_to_end(&mut message)
-                        .context(format!("File {:?} cannot be read!", &path))?;
-                }
-                // from stdin, otherwise
+/// Run a subcommand
+fn run_command(cmd: &Command, global: &GlobalOpt) -> Fallible<()> {
+    let quiet = global.quiet;
+    match cmd {
+        Command::Encrypt(opts) => encrypt(opts, global),
+        Command::Send {
+            route,
+            deliver,
+            json,
+        } => send(route, deliver, *json, global),
+        Command::Remailers(RemailersCommand::List { selectors, format }) => {
+            list_remailers(selectors, *format, global)
+        }
+        Command::Remailers(RemailersCommand::Show { name }) => show_remailer(name, global),
+        Command::Remailers(RemailersCommand::Enable { names, into }) => {
+            enable_remailers(names, into, true, global)
+        }
+        Command::Remailers(RemailersCommand::Disable { names, into }) => {
+            enable_remailers(names, into, false, global)
+        }
+        Command::Keys(KeysCommand::List) => list_keys(global),
+        Command::Keys(KeysCommand::Verify { all }) => verify_keys(*all, global),
+        Command::Keys(KeysCommand::Import {
+            files,
+            into,
+            policy,
+        }) => import_key_files(files, into, *policy, global),
+        Command::Peel { input, keys } => peel_message(input, keys, quiet),
+        Command::Serve {
+            maildir,
+            key,
+            homedir,
+            deliver,
+        } => serve(maildir, key, homedir, deliver, quiet),
+        Command::Selftest => selftest(quiet),
+        Command::Doctor => doctor(global),
+        Command::Config(ConfigCommand::Check) => check_config_layers(global),
+        Command::Config(ConfigCommand::Migrate { file }) => migrate_config_file(file),
+        Command::Config(ConfigCommand::Merge { file, into, policy }) => {
+            merge_config_file(file, into, *policy, global)
+        }
+        Command::Config(ConfigCommand::ImportPubring {
+            files,
+            into,
+            policy,
+        }) => import_pubring(files, into, *policy),
+        Command::Config(ConfigCommand::Refresh {
+            mailbox,
+            into,
+            policy,
+        }) => refresh_remailers(mailbox, into, *policy, global),
+        Command::Config(ConfigCommand::Request { from, output, all }) => {
+            request_updates(from, output, *all, global)
+        }
+    }
+}
+
+/// Encrypt a message through the route of each copy, and write the copies into stdout or the
+/// output directory
+fn encrypt(opts: &EncryptOpt, global: &GlobalOpt) -> Fallible<()> {
+    let (profile, named_chains) = load_route_profile(&opts.route)?;
+    let route = opts.route.with_profile(&profile);
+    let format = match opts.format {
+        Some(format) => format,
+        None => profile
+            .format()
+            .context(ErrorKind::Config)?
+            .unwrap_or(OutputFormat::Cypherpunk),
+    };
+
+    let config = load_trusted_config(global)?;
+    let plan = plan_routes(&route, &named_chains, &config)?;
+
+    // Save the plan, if asked
+    if let Some(path) = &opts.plan_out {
+        plan.save(path).context(ErrorKind::Io)?;
+        info!("Routing plan saved in {}", path.to_string_lossy());
+        if !opts.dry_run {
+            return Ok(());
+        }
+    }
+
+    // Retrieve the message to send
+    let message = read_input(&route.input)?;
+
+    // Only explain the routes, if asked
+    if opts.dry_run {
+        for (index, route) in plan.copies.iter().enumerate() {
+            print_route(index, route, &message)?;
+        }
+        return Ok(());
+    }
+
+    let core = init_core(&plan, &config.remailers, global.quiet)?;
+
+    // if an output path is given, create the directory
+    if let Some(path) = &opts.output {
+        create_dir_all(path)
+            .context(format!("Cannot create directory for {:?}", path))
+            .context(ErrorKind::Io)?;
+    }
+
+    info!("Encrypting...");
+    let mut copies = Vec::new();
+    let mut failures = Vec::new();
+    for (index, route) in plan.copies.iter().enumerate() {
+        let copy = encrypt_copy(&core, index, route, &message).and_then(|msg| {
+            // Format the final message
+            let msg = format_msg(format, msg)
+                .context(format!("Failed to format message n°{}", index + 1))
+                .context(ErrorKind::Backend)?;
+            let mut copy = EncryptedCopy::new(index, route.emails(), format, msg);
+            // Write the formatted message into a file, or else into stdout
+            match &opts.output {
+                Some(dir) => copy.file = Some(write_message(dir, &copy, format)?),
+                None if opts.json => {}
                 None => {
-                    println!("\nType your message:");
-                    stdin()
-                        .lock()
-                        .read_to_end(&mut message)
-                        .context("Cannot read the standard input!")?;
-                    println!();
+                    info!("Encrypted message n°{}:", copy.copy);
+                    outln!("{}", copy.payload);
                 }
+            }
+            Ok(copy)
+        });
+        match copy {
+            Ok(copy) => copies.push(copy),
+            Err(err) => failures.push((index, err)),
+        }
+    }
+    if opts.json {
+        print_copies(&copies)?;
+    }
+    check_copies(copies.len(), failures)
+}
+
+/// Encrypt a message through the route of each copy, and hand each copy to the delivery command
+fn send(opts: &RouteOpt, deliver: &str, json: bool, global: &GlobalOpt) -> Fallible<()> {
+    let (profile, named_chains) = load_route_profile(opts)?;
+    let route = opts.with_profile(&profile);
+
+    let config = load_trusted_config(global)?;
+    let plan = plan_routes(&route, &named_chains, &config)?;
+    let message = read_input(&route.input)?;
+    let core = init_core(&plan, &config.remailers, global.quiet)?;
+
+    info!("Sending...");
+    let mut copies = Vec::new();
+    let mut failures = Vec::new();
+    for (index, route) in plan.copies.iter().enumerate() {
+        let copy = encrypt_copy(&core, index, route, &message).and_then(|msg| {
+            let (to, body) = read_envelope(&msg)?;
+            let email = Delivery {
+                to,
+                headers: Vec::new(),
+                body,
             };
+            run_delivery(deliver, &email)
+                .context(format!(
+                    "Cannot send message n°{} to {}",
+                    index + 1,
+                    email.to
+                ))
+                .context(ErrorKind::Io)?;
+            info!("Message n°{} sent to {}", index + 1, email.to);
+            Ok(EncryptedCopy::new(
+                index,
+                route.emails(),
+                OutputFormat::Cypherpunk,
+                msg,
+            ))
+        });
+        match copy {
+            Ok(copy) => copies.push(copy),
+            Err(err) => failures.push((index, err)),
+        }
+    }
+    if json {
+        print_copies(&copies)?;
+    }
+    check_copies(copies.len(), failures)
+}
 
-            // if an output path is given, create the directory
-            if let Some(path) = &opts.output {
-                create_dir_all(path).context(format!("Cannot create directory for {:?}", path))?;
-            }
+/// Print the encrypted copies as a JSON document
+fn print_copies(copies: &[EncryptedCopy]) -> Fallible<()> {
+    let document = serde_json::json!({ "copies": copies });
+    outln!("{}", serde_json::to_string_pretty(&document)?);
+    Ok(())
+}
 
-            println!("Encrypting...");
-
-            // Reverse the chain, we start the encryption for the farther remailer, etc...
-            let mut chain = (&opts.chain).clone();
-            chain.reverse();
-
-            // Encrypting...
-            red.map(|index| {
-                println!("Encrypting message n°{}...", index + 1);
-                // Build a remailer chain
-                let chain = make_chain(&chain, &remmap, &mut rng)
-                    .context(format!("Can't build a chain for message n°{}!", index))?;
-                println!("Selected chain: {}", &chain.join(", "));
-                // Encrypt the message for this chain + given headers
-                Ok(core
-                    .encrypt_message(&chain, &opts.headers, message.clone())
-                    .context(format!(
-                        "Failed to encrypt message n°{} with chain {}",
-                        index,
-                        &chain.join(", ")
-                    ))?)
-            })
-            .enumerate()
-            .map(|(index, res): (_, Fallible<Vec<u8>>)| -> Fallible<()> {
-                match res {
-                    // Case of valid message
-                    Ok(msg) => {
-                        // Case of valid utf-8 message (it should because it is an arbored PGP message)
-                        if let Ok(msg) = String::from_utf8(msg) {
-                            // Format the final message
-                            let msg = format_msg(opts.format, msg)
-                                .context(format!("Failed to format message n°{}", index))?;
-
-                            // Write the formatted message into stdout or file
-                            match opts.output.clone() {
-                                // Case of file output
-                                Some(mut path) => {
-                                    // Make the output file path
-                                    path.push(
-                                        format!(
-                                            "redundancy_{}.{}",
-                                            index + 1,
-                                            &opts.format.extension()
-                                        )
-                                        .as_str(),
-                                    );
-                                    // Write the message
-                                    let mut file = File::create(&path)
-                                        .context(format!("Cannot create file {:?}", &path))?;
-                                    file.write_all(msg.as_bytes())
-                                        .context(format!("Cannot write in file {:?}", &path))?;
-                                    // Write the output path into stdout
-                                    println!(
-                                        "Encrypted message n°{} in {}",
-                                        index + 1,
-                                        path.to_string_lossy()
-                                    )
-                                }
-                                // Case of stdout output - Just print the message
-                                None => println!("Encrypted message n°{}:\n{}", index + 1, msg),
-                            }
-                        } else {
-                            // Return error if the message is not utf-8 encoded
-                            return Err(err_msg(
-                                "Internal Error, encrypted message is not a valid utf-8 string.",
-                            ));
-                        }
-                    }
-                    // Case of error during encryption
-                    err => {
-                        err.context(format!(
-                            "Message n°{}: Ignored, error occured before formatting!",
-                            index + 1
-                        ))?;
-                    }
+/// Fail if some copies failed, given by message index: with the error of the first one if none
+/// succeeded, else as a partial failure. The other failures are logged.
+fn check_copies(succeeded: usize, mut failures: Vec<(usize, FError)>) -> Fallible<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    let total = succeeded + failures.len();
+    let first = if succeeded == 0 {
+        Some(failures.remove(0).1)
+    } else {
+        None
+    };
+    for (index, err) in &failures {
+        error!("message n°{}: {}", index + 1, causes(err));
+    }
+    match first {
+        Some(err) => Err(err),
+        None => Err(err_msg(format!(
+            "{} of {} message(s) failed!",
+            total - succeeded,
+            total
+        ))
+        .context(ErrorKind::Partial)
+        .into()),
+    }
+}
+
+impl RouteOpt {
+    /// Return these options, the ones not given being filled from a profile
+    fn with_profile(&self, profile: &Profile) -> RouteOpt {
+        let mut opts = self.clone();
+        // The chain given on the command line, by `--chain` or `--hops`, replaces the profile's
+        if opts.chain.is_empty() && opts.hops.is_none() {
+            opts.chain = profile.chain.clone().unwrap_or_default();
+            opts.hops = profile.hops;
+        }
+        if opts.headers.is_empty() {
+            opts.headers = profile.headers.clone().unwrap_or_default();
+        }
+        opts.redundancy = opts.redundancy.or(profile.redundancy);
+        if !opts.allow_missing && !opts.no_allow_missing {
+            opts.allow_missing = profile.allow_missing.unwrap_or(false);
+        }
+        opts
+    }
+}
+
+/// Return the profile given with `--profile`, or else the default one if any (an empty one
+/// otherwise), and the chains of the profiles, by name
+fn load_profile(name: &Option<String>) -> Fallible<(Profile, HashMap<String, Vec<String>>)> {
+    let settings = load_settings(&settings_paths()).context(ErrorKind::Config)?;
+    let profile = match name {
+        Some(name) => settings.profile(name).context(ErrorKind::Usage)?.clone(),
+        None => settings
+            .profiles
+            .get(DEFAULT_PROFILE)
+            .cloned()
+            .unwrap_or_default(),
+    };
+    Ok((profile, settings.named_chains()))
+}
+
+/// Return the profile of the route options (see `load_profile`), an empty one with `--plan-in`:
+/// the plan gives the routes
+fn load_route_profile(opts: &RouteOpt) -> Fallible<(Profile, HashMap<String, Vec<String>>)> {
+    match opts.plan_in {
+        Some(_) => Ok(Default::default()),
+        None => load_profile(&opts.profile),
+    }
+}
+
+/// Load the config from its layers, refusing the unauthenticated ones
+fn load_trusted_config(global: &GlobalOpt) -> Fallible<RemailerConfig> {
+    info!("Config loading...");
+    let layers =
+        load_layers(&config_paths(), global.config.as_deref()).context(ErrorKind::Config)?;
+    print_layers(&layers);
+    let errors = verify_config(&layers, global).context(ErrorKind::Config)?;
+    if let Some(error) = errors.into_iter().next() {
+        return Err(error
+            .context("Refusing an unauthenticated config, see `--insecure-config`")
+            .into());
+    }
+    Ok(load_layered_config(&layers).context(ErrorKind::Config)?)
+}
+
+/// Plan the route of each redundancy message: from the given plan, or from the chain. Each route
+/// is checked: its headers, and the capabilities of its remailers.
+fn plan_routes(
+    opts: &RouteOpt,
+    named_chains: &HashMap<String, Vec<String>>,
+    config: &RemailerConfig,
+) -> Fallible<Plan> {
+    let plan = match &opts.plan_in {
+        Some(path) => {
+            info!("Loading the routing plan...");
+            let plan = Plan::load(path).context(ErrorKind::Io)?;
+            // A remailer key that can't be read keeps its kind
+            plan.check(&config.remailers).map_err(|err| {
+                let kind = ErrorKind::of(&err).unwrap_or(ErrorKind::Usage);
+                err.context(format!("The plan {:?} doesn't match the config!", path))
+                    .context(kind)
+            })?;
+            plan
+        }
+        None => {
+            // Init a random generator (seeded one, if asked) and the remailer map from config
+            let mut rng = match opts.seed {
+                Some(seed) => {
+                    warn!("the remailer selection is seeded, it's not random anymore!");
+                    StdRng::seed_from_u64(seed)
                 }
-                Ok(())
-            })
-            .collect::<Fallible<Vec<()>>>()?; // Collect all errors in one fallible
-            Ok(())
-        }) // In all errors case, don't panic just print the errors
-        .unwrap_or_else(print_errors);
+                None => StdRng::from_entropy(),
+            };
+            let remmap = remailer_map(config.remailers.clone());
+
+            // Expand the chain to the wanted number of hops
+            let chain = expand_named_chains(&opts.chain, named_chains).context(ErrorKind::Usage)?;
+            let chain = expand_chain(&chain, opts.hops).context(ErrorKind::Usage)?;
+            debug!("Expanded chain: {}", chain.join(", "));
+            let copies = (0..opts.redundancy.unwrap_or(1))
+                .map(|index| {
+                    // Build a remailer chain
+                    let chain = make_chain(
+                        &chain,
+                        &remmap,
+                        &config.remailers,
+                        opts.allow_missing,
+                        &mut rng,
+                    )
+                    .context(format!("Can't build a chain for message n°{}!", index + 1))
+                    .context(ErrorKind::Usage)?;
+                    Ok(Route::new(&chain, &opts.headers, &config.remailers)
+                        .context(ErrorKind::Usage)?)
+                })
+                .collect::<Fallible<Vec<Route>>>()?;
+            Plan { copies }
+        }
+    };
+    for (index, route) in plan.copies.iter().enumerate() {
+        info!(
+            "Selected chain for message n°{}: {}",
+            index + 1,
+            route.emails().join(", ")
+        );
+    }
+
+    // Check the headers and the remailers' capabilities of each route
+    for (index, route) in plan.copies.iter().enumerate() {
+        let warnings = route
+            .check(&config.remailers)
+            .context(format!("Invalid route for message n°{}!", index + 1))
+            .context(ErrorKind::Usage)?;
+        for warning in warnings {
+            warn!("message n°{}: {}", index + 1, warning);
+        }
+    }
+    Ok(plan)
 }
 
-/// Import remailers' key in the Cypherpunk core from a vec of remailer.
-/// It will only import enabled remailers.
-fn import_keys(core: &impl Cypherpunk, remailers: &[Remailer]) -> Fallible<()> {
-    // Retrieve enabled remailers' keys
-    let keys: Vec<Vec<u8>> = remailers
+/// Init the Cypherpunk core (with its PGP backend) and import the keys of the remailers of the
+/// routes of a plan
+fn init_core(plan: &Plan, remailers: &[Remailer], quiet: bool) -> Fallible<impl Cypherpunk> {
+    // Init infra (the PGP backend)
+    let pgp_back = init_pgp_back(quiet);
+    // Init the domain (the CypherpunkCore)
+    let core = CypherpunkCore::new(pgp_back);
+
+    // Import remailers' key
+    info!("Importing remailers' key...");
+    let emails: Vec<String> = plan.copies.iter().flat_map(Route::emails).collect();
+    let remailers: Vec<Remailer> = remailers
         .iter()
-        .filter_map(|remailer| {
-            if remailer.is_enabled() {
-                remailer.as_key().ok()
-            } else {
-                None
+        .filter(|remailer| emails.contains(&remailer.email))
+        .cloned()
+        .collect();
+    import_keys(&core, &remailers)
+        .context("Importation of remailers' key failed")
+        .context(ErrorKind::KeyImport)?;
+    Ok(core)
+}
+
+/// Encrypt the message of a copy for its route and its headers, as a Cypherpunk message
+fn encrypt_copy(
+    core: &impl Cypherpunk,
+    index: usize,
+    route: &Route,
+    message: &[u8],
+) -> Fallible<String> {
+    info!("Encrypting message n°{}...", index + 1);
+    let msg = core
+        .encrypt_route(&route.hops, message.to_vec())
+        .context(format!(
+            "Failed to encrypt message n°{} with chain {}",
+            index + 1,
+            route.emails().join(", ")
+        ))
+        .context(ErrorKind::Backend)?;
+    // It should be valid utf-8 because it is an armored PGP message
+    Ok(String::from_utf8(msg)
+        .map_err(|_| err_msg("Internal Error, encrypted message is not a valid utf-8 string."))
+        .context(ErrorKind::Backend)?)
+}
+
+/// Write a formatted copy into a file of the output directory, return its path
+fn write_message(dir: &Path, copy: &EncryptedCopy, format: OutputFormat) -> Fallible<PathBuf> {
+    // Make the output file path
+    let path = dir.join(format!("redundancy_{}.{}", copy.copy, format.extension()));
+    // Write the message
+    let mut file = File::create(&path)
+        .context(format!("Cannot create file {:?}", &path))
+        .context(ErrorKind::Io)?;
+    file.write_all(copy.payload.as_bytes())
+        .context(format!("Cannot write in file {:?}", &path))
+        .context(ErrorKind::Io)?;
+    info!(
+        "Encrypted message n°{} in {}",
+        copy.copy,
+        path.to_string_lossy()
+    );
+    Ok(path)
+}
+
+/// Print the remailers of the config matching the selectors, all of them without any
+fn list_remailers(selectors: &[String], format: ListFormat, global: &GlobalOpt) -> Fallible<()> {
+    let config = load_trusted_config(global)?;
+    let remailers: Vec<&Remailer> = if selectors.is_empty() {
+        config.remailers.iter().collect()
+    } else {
+        let (_, named_chains) = load_profile(&None)?;
+        let selectors = expand_chain(&expand_named_chains(selectors, &named_chains)?, None)?;
+        select_remailers(&selectors, &config.remailers)?
+    };
+    out!("{}", format_rows(&remailer_rows(&remailers), format)?);
+    Ok(())
+}
+
+/// Print everything known about a remailer of the config
+fn show_remailer(name: &str, global: &GlobalOpt) -> Fallible<()> {
+    let config = load_trusted_config(global)?;
+    let remailer = find_remailer(&config.remailers, name)?;
+    outln!("Email: {}", remailer.email);
+    outln!("Aliases: {}", remailer.name.join(", "));
+    outln!("Enabled: {}", if remailer.enable { "yes" } else { "no" });
+    outln!(
+        "Capabilities: {}",
+        if remailer.capabilities.is_empty() {
+            "unknown".to_string()
+        } else {
+            remailer.capabilities.join(" ")
+        }
+    );
+    match remailer.as_key() {
+        Ok(key) => {
+            outln!("Key fingerprint: {}", packet::fingerprint(&key)?);
+            for user_id in packet::user_ids(&key)? {
+                outln!("Key user ID: {}", user_id);
             }
+        }
+        Err(err) => outln!("Key: invalid, {}", err),
+    }
+    outln!(
+        "Pinned fingerprint: {}",
+        remailer.pinned_fingerprint.as_deref().unwrap_or("none")
+    );
+    match &remailer.stats {
+        Some(stats) => outln!(
+            "Statistics: latency {}, uptime {}",
+            stats.latency,
+            stats.uptime
+        ),
+        None => outln!("Statistics: none"),
+    }
+    Ok(())
+}
+
+/// Enable or disable remailers in a config file: the given one, or else the user config
+fn enable_remailers(
+    names: &[String],
+    into: &Option<PathBuf>,
+    enable: bool,
+    global: &GlobalOpt,
+) -> Fallible<()> {
+    let config = load_trusted_config(global)?;
+    let emails = names
+        .iter()
+        .map(|name| Ok(find_remailer(&config.remailers, name)?.email.clone()))
+        .collect::<Fallible<Vec<String>>>()?;
+
+    let target = match into {
+        Some(path) => path.clone(),
+        // The user config directory is the last one
+        None => config_dirs()
+            .get(1)
+            .map(|dir| config_file(dir))
+            .ok_or_else(|| err_msg("No user config directory, give a config with `--into`"))?,
+    };
+    let exists = target.is_file();
+    let mut value = if exists {
+        read_config(&target)?
+    } else {
+        serde_json::json!({ "remailers": [] })
+    };
+    for email in &emails {
+        set_enable(&mut value, email, enable)
+            .context(format!("Cannot change the config {:?}", target))?;
+    }
+    if exists {
+        let backup = write_config(&target, &value)?;
+        info!("{:?} updated (old config in {:?}).", target, backup);
+        warn_rewritten(&target);
+    } else {
+        create_dir_all(config_dir(&target))
+            .context(format!("Cannot create the directory of {:?}", target))?;
+        let format = ConfigFormat::detect(&target, "{");
+        std::fs::write(&target, format.serialize(&value)?)
+            .context(format!("Cannot write the config {:?}", target))?;
+        info!("{:?} created.", target);
+    }
+    for email in emails {
+        info!(
+            "  {} {}",
+            if enable { "enabled" } else { "disabled" },
+            email
+        );
+    }
+    Ok(())
+}
+
+/// Return a remailer of the config, enabled or not, by its alias or its email
+fn find_remailer<'a>(remailers: &'a [Remailer], name: &str) -> Fallible<&'a Remailer> {
+    remailers
+        .iter()
+        .find(|remailer| {
+            remailer.email.eq_ignore_ascii_case(name)
+                || remailer.name.iter().any(|alias| alias == name)
         })
-        .collect();
+        .ok_or_else(|| err_msg(format!("Unknown remailer `{}` in the config!", name)))
+        .context(ErrorKind::Usage)
+        .map_err(FError::from)
+}
 
-    // Import keys in the Cypherpunk Core
-    Ok(core
-        .import_keys(keys)
-        .context("Failed to import keys in the Cypherpunk core!")?)
+/// Print the key of each remailer of the config
+fn list_keys(global: &GlobalOpt) -> Fallible<()> {
+    let config = load_trusted_config(global)?;
+    for remailer in &config.remailers {
+        match remailer.as_key() {
+            Ok(key) => {
+                let pinned = if remailer.pinned_fingerprint.is_some() {
+                    " (pinned)"
+                } else {
+                    ""
+                };
+                outln!(
+                    "{} {}{}",
+                    remailer.email,
+                    packet::fingerprint(&key)?,
+                    pinned
+                );
+                for user_id in packet::user_ids(&key)? {
+                    outln!("    {}", user_id);
+                }
+            }
+            Err(err) => outln!("{} invalid key: {}", remailer.email, err),
+        }
+    }
+    Ok(())
+}
+
+/// Verify the keys of the remailers of the config, and print the outcome for each one
+fn verify_keys(all: bool, global: &GlobalOpt) -> Fallible<()> {
+    let config = load_trusted_config(global)?;
+    let mut failures = 0;
+    for remailer in config
+        .remailers
+        .iter()
+        .filter(|remailer| all || remailer.enable)
+    {
+        match check_key(remailer) {
+            Ok(()) => outln!("  [ OK ] {}", remailer.email),
+            Err(err) => {
+                failures += 1;
+                outln!("  [FAIL] {}: {}", remailer.email, err);
+            }
+        }
+    }
+    if failures == 0 {
+        outln!("The keys are valid.");
+        Ok(())
+    } else {
+        Err(err_msg(format!("{} invalid key(s)!", failures))
+            .context(ErrorKind::KeyImport)
+            .into())
+    }
+}
+
+/// Import key files into the local remailer list, the new keys being reviewed like a new list
+fn import_key_files(
+    files: &[PathBuf],
+    into: &Option<PathBuf>,
+    policy: MergePolicy,
+    global: &GlobalOpt,
+) -> Fallible<()> {
+    let mut keys = Vec::new();
+    for path in files {
+        let keyring = armor::dearmor(&read_file(path)?)
+            .context(format!("File {:?} is not a valid OpenPGP key!", path))
+            .context(ErrorKind::KeyImport)?;
+        let split = packet::split_keys(&keyring)
+            .context(format!("File {:?} is not a valid OpenPGP key!", path))
+            .context(ErrorKind::KeyImport)?;
+        keys.extend(split.into_iter().map(<[u8]>::to_vec));
+    }
+
+    let target = local_list(into, global)?;
+    let local = load_config(&target).context(ErrorKind::Config)?;
+    let (remailers, unknown) =
+        update_keys(&local.remailers, &keys).context(ErrorKind::KeyImport)?;
+    for user_ids in unknown {
+        warn!(
+            "the key of {} is not for a known remailer, ignored",
+            user_ids
+        );
+    }
+    let new = RemailerConfig {
+        remailers,
+        ..local.clone()
+    };
+    outln!("Changes to {:?}:", target);
+    review_changes(&target, local, new, policy, false)
+}
+
+/// Read all the content of a file
+fn read_file(path: &Path) -> Fallible<Vec<u8>> {
+    let mut content: Vec<u8> = Vec::new();
+    let mut file = File::open(path).context(format!("File {:?} cannot be open!", path))?;
+    file.read_to_end(&mut content)
+        .context(format!("File {:?} cannot be read!", path))?;
+    Ok(content)
+}
+
+/// Retrieve the message from the input file, or from stdin
+fn read_input(input: &Option<PathBuf>) -> Fallible<Vec<u8>> {
+    let mut message: Vec<u8> = Vec::new();
+    match input {
+        // from path, if given
+        Some(path) => {
+            info!("Retrieving message from file...");
+            message = read_file(path).context(ErrorKind::Io)?;
+        }
+        // from stdin, otherwise
+        None => {
+            info!("Type your message:");
+            stdin()
+                .lock()
+                .read_to_end(&mut message)
+                .context("Cannot read the standard input!")
+                .context(ErrorKind::Io)?;
+        }
+    };
+    Ok(message)
+}
+
+/// Peel an encrypted message with the secret keys of the remailers and print its path
+fn peel_message(input: &Option<PathBuf>, keys: &[PathBuf], quiet: bool) -> Fallible<()> {
+    // One PGP backend, with its own home directory, per remailer; the home directories holding
+    // the secret keys are deleted on return
+    let mut remailers = HashMap::new();
+    let mut homedirs = Vec::new();
+    for path in keys {
+        let key = read_file(path).context(ErrorKind::Io)?;
+        let user_ids = armor::dearmor(&key)
+            .and_then(|key| packet::user_ids(&key))
+            .context(format!("File {:?} is not a valid OpenPGP key!", path))
+            .context(ErrorKind::KeyImport)?;
+        let email = user_ids
+            .first()
+            .map(|user_id| packet::user_id_email(user_id).to_lowercase())
+            .ok_or_else(|| err_msg(format!("The key {:?} has no user ID!", path)))
+            .context(ErrorKind::KeyImport)?;
+
+        let homedir = tempdir()
+            .context("Cannot create a temporary GnuPG home directory!")
+            .context(ErrorKind::Backend)?;
+        let pgp = GPGBackend::with_homedir(homedir.path().to_path_buf(), None, quiet);
+        homedirs.push(homedir);
+        pgp.import_key(key)
+            .context(format!("Cannot import the secret key of `{}`", email))
+            .context(ErrorKind::KeyImport)?;
+        info!("Secret key of `{}` imported", email);
+        remailers.insert(email, pgp);
+    }
+
+    // Peel the message
+    let message = read_input(input)?;
+    let peeled =
+        peel(&remailers, &String::from_utf8_lossy(&message)).context(ErrorKind::Backend)?;
+
+    outln!("Path of the message:");
+    for (index, step) in peeled.steps.iter().enumerate() {
+        outln!("  {}. {} sends to {}", index + 1, step.remailer, step.to);
+        for header in &step.headers {
+            outln!("      honouring {}", header);
+        }
+    }
+    match peeled.delivery {
+        Some(delivery) => outln!("\nDelivered message:\n{}", delivery),
+        None => outln!("\nThe message was dropped by the last remailer (`Null:` header)."),
+    }
+    Ok(())
 }
 
-/// Format a message for a particular OutputFormat, can fail.
-fn format_msg(format: OutputFormat, msg: String) -> Fallible<String> {
-    match format {
-        OutputFormat::Cypherpunk => Ok(msg),
-        OutputFormat::Mailto => {
-            Ok(format_mailto(msg).context("Failed to format the message in mailto URL")?)
-        }
-        OutputFormat::EML => {
-            Ok(format_eml(msg).context("Failed to format the message in a EML email")?)
-        }
-        // In the future case of unimplemented format...
-        #[allow(unreachable_patterns)]
-        other => Err(err_msg(format!("Format {:?} not yet implemented!", other))),
-    }
-}
-
-/// Format a given message to an EML-formatted email
-fn format_eml(message: String) -> Fallible<String> {
-    // Get address and message body
-    let (addr, message) = format_helper(message)?;
-
-    // Format it and return!
-    Ok(format!(
-        "MIME-Version: 1.0\n\
-    Content-Type: text/plain; charset=utf-8\n\
-    To: {}\n\
-    \n\
-    {}",
-        addr, message
-    ))
-}
-
-/// Format a given message to an mailto URL
-fn format_mailto(message: String) -> Fallible<String> {
-    // Get address and message body
-    let (addr, message) = format_helper(message)?;
-
-    // Encode body into utf-8 percent encode (to avoid special URL token)
-    let body = utf8_percent_encode(message.as_str(), NON_ALPHANUMERIC).to_string();
-    // Make it URL and return!
-    Ok(format!("mailto:{}?body={}", addr, body))
-}
-
-/// Get from a given message, the message's recipient and the message's body
-fn format_helper(mut message: String) -> Fallible<(String, String)> {
-    // Find the `Anon-To` header
-    let addr_start = message
-        .find("Anon-To: ")
-        .ok_or_else(|| err_msg("Invalid Cypherpunk message (Anon-To header missing)"))?; // Find the address start
-    message.drain(..addr_start + 9); // Drop all chars before email address
-    let addr_end = message.find('\n').ok_or_else(|| {
-        err_msg("Invalid Cypherpunk message (Anon-To header is the only line in message)")
-    })?; // Find the address end
-    let addr: String = message.drain(..addr_end).collect(); // Save the email address in the var, and drop all chars before message
-                                                            // Look for a body (always separated by two line return to the headers)
-    let body_start = message
-        .find("\n\n")
-        .ok_or_else(|| err_msg("Invalid Cypherpunk message (Body not found)"))?
-        + 2;
-    message.drain(..body_start); // Drop all the char before the body
-                                 // Return the recipient address and the remaining message
-    Ok((addr, message))
-}
-
-/// Retrieve from path given the remailer config (using serde-json)
-fn load_config<P: AsRef<Path>>(path: P) -> Fallible<RemailerConfig> {
-    Ok(serde_json::from_reader(
-        File::open(path.as_ref()).context(format!("Failed to open {:?}!", path.as_ref()))?,
-    )
-    .context(format!(
-        "Failed to parse {:?} as a json config!",
-        path.as_ref()
-    ))?)
-}
-
-/// Make a chain of remailers with the given "user-defined" chain
-fn make_chain(
-    chain: &[String],
-    remmap: &HashMap<String, String>,
-    rng: &mut ThreadRng,
-) -> Fallible<Vec<String>> {
-    // New chain holder
-    let mut rchain = Vec::new();
-    // For all remailers in the actual chain:
-    for rem in chain {
-        // Case of "randomly chosen" remailer
-        if rem == "*" {
-            // Return one remailer address from the map
-            match remmap.values().choose(rng) {
-                Some(email) => rchain.push(email.clone()),
-                None => return Err(err_msg("Can't choose a remailer randomly...")),
+/// Run a Type-I remailer on the emails of a Maildir, or on one email from stdin
+fn serve(
+    maildir: &Option<PathBuf>,
+    key: &Option<PathBuf>,
+    homedir: &Option<PathBuf>,
+    deliver: &str,
+    quiet: bool,
+) -> Fallible<()> {
+    // The PGP backend holding the secret key of the remailer, in a temporary home directory
+    // deleted on return if none is given
+    let temp_homedir;
+    let homedir = match homedir {
+        Some(homedir) => homedir.clone(),
+        None => {
+            temp_homedir = tempdir()
+                .context("Cannot create a temporary GnuPG home directory!")
+                .context(ErrorKind::Backend)?;
+            temp_homedir.path().to_path_buf()
+        }
+    };
+    let pgp = GPGBackend::with_homedir(homedir, None, quiet);
+    if let Some(path) = key {
+        pgp.import_key(read_file(path).context(ErrorKind::Io)?)
+            .context("Cannot import the secret key of the remailer")
+            .context(ErrorKind::KeyImport)?;
+    }
+    let mut server = Server::new(pgp, deliver);
+
+    // The email from stdin, or else the new emails of the Maildir; while the delays of the
+    // queued emails run, the Maildir is read again for the emails coming meanwhile
+    if maildir.is_none() {
+        let email = String::from_utf8_lossy(&read_input(&None)?).into_owned();
+        match server.receive(&email, None).context(ErrorKind::Backend)? {
+            Some((delay, delivery)) => info!(
+                "Email from stdin: to {} in {} seconds",
+                delivery.to,
+                delay.as_secs()
+            ),
+            None => info!("Email from stdin: dropped (`Null:` header)"),
+        }
+    }
+    loop {
+        if let Some(maildir) = maildir {
+            for path in maildir_new(maildir).context(ErrorKind::Io)? {
+                if !server.is_queued(&path) {
+                    receive_maildir_email(&mut server, &path)?;
+                }
             }
-        // Case of a named remailer
+        }
+
+        // Deliver the emails whose delay is over, then wait for the next one
+        for delivery in server.deliver_due().context(ErrorKind::Io)? {
+            info!("Email to {} delivered", delivery.to);
+        }
+        let wait = match server.next_time() {
+            Some(time) => time.duration_since(SystemTime::now()).unwrap_or_default(),
+            None => return Ok(()),
+        };
+        sleep(match maildir {
+            Some(_) => wait.min(MAILDIR_POLL),
+            None => wait,
+        });
+    }
+}
+
+/// Process a new email of a Maildir: queue its result, or mark it as processed if dropped or
+/// refused (a bad email must not stop the remailer)
+fn receive_maildir_email(server: &mut Server<impl PGPBackend>, path: &Path) -> Fallible<()> {
+    let name = path.to_string_lossy();
+    let email = String::from_utf8_lossy(&read_file(path).context(ErrorKind::Io)?).into_owned();
+    match server.receive(&email, Some(path)) {
+        Ok(Some((delay, delivery))) => {
+            info!(
+                "Email {}: to {} in {} seconds",
+                name,
+                delivery.to,
+                delay.as_secs()
+            );
+            return Ok(());
+        }
+        Ok(None) => info!("Email {}: dropped (`Null:` header)", name),
+        Err(err) => warn!("Email {}: ignored, {}", name, err),
+    }
+    Ok(maildir_done(path).context(ErrorKind::Io)?)
+}
+
+/// Check GnuPG then the PGP backend end to end, and print the outcome of each stage
+fn selftest(quiet: bool) -> Fallible<()> {
+    info!("Self-testing the PGP backend...");
+    // GnuPG itself, then the backend using it
+    let mut stages = vec![Stage {
+        name: "gpg found",
+        outcome: gpg::version(),
+    }];
+    if let Ok(line) = &stages[0].outcome {
+        let outcome = gpg::check_version(line);
+        stages.push(Stage {
+            name: "version",
+            outcome,
+        });
+    }
+    if stages.iter().all(Stage::passed) {
+        stages.extend(backend_selftest::selftest(|homedir| {
+            GPGBackend::with_homedir(homedir.to_path_buf(), None, quiet)
+        }));
+    }
+
+    // Report every stage, the ones after a failure are skipped
+    for stage in &stages {
+        match &stage.outcome {
+            Ok(report) => outln!("  [PASS] {}: {}", stage.name, report),
+            Err(err) => outln!("  [FAIL] {}: {}", stage.name, err),
+        }
+    }
+    let names = ["gpg found", "version"].iter().chain(STAGES);
+    for name in names.skip(stages.len()) {
+        outln!("  [SKIP] {}", name);
+    }
+    if stages.len() == 2 + STAGES.len() && stages.iter().all(Stage::passed) {
+        outln!("Self-test passed, the PGP backend works.");
+        Ok(())
+    } else {
+        Err(err_msg("Self-test failed!")
+            .context(ErrorKind::Backend)
+            .into())
+    }
+}
+
+/// Diagnose the environment and the config, and print the outcome of each check
+fn doctor(global: &GlobalOpt) -> Fallible<()> {
+    let quiet = global.quiet;
+    // The kind of the first failure gives the exit code
+    let mut failures = 0;
+    let mut first_kind = None;
+    let mut report = |name: &str, kind: ErrorKind, outcome: Fallible<String>| -> Fallible<()> {
+        match outcome {
+            Ok(report) => outln!("  [ OK ] {}: {}", name, report),
+            Err(err) => {
+                failures += 1;
+                first_kind.get_or_insert(kind);
+                outln!("  [FAIL] {}: {}", name, err);
+            }
+        }
+        Ok(())
+    };
+
+    // GnuPG and its temporary files
+    info!("Checking GnuPG...");
+    match gpg::version() {
+        Ok(line) => {
+            report("gpg", ErrorKind::Backend, Ok(line.clone()))?;
+            report("gpg version", ErrorKind::Backend, gpg::check_version(&line))?;
+        }
+        Err(err) => report("gpg", ErrorKind::Backend, Err(err))?,
+    }
+    let pgp = GPGBackend::new(None, quiet);
+    report(
+        "temporary directory",
+        ErrorKind::Backend,
+        if pgp.uses_fallback_keyring() {
+            Err(err_msg(format!(
+                "{:?} is not writable, the keys go to the shared keyring {:?} and nothing can be encrypted",
+                pgp.temp_dir(),
+                pgp.temp_dir().join(gpg::FALLBACK_KEYRING)
+            )))
         } else {
-            // If the remailer name is known in the map, we add it in the chain, otherwise we ignore
-            // it and print a message in the stderr
-            match remmap.get(rem) {
-                Some(email) => rchain.push(email.clone()),
-                None => eprintln!("Ignored remailer `{}` in the chain!", rem),
+            Ok(format!("{:?} is writable", pgp.temp_dir()))
+        },
+    )?;
+
+    // The config layers, some of them relative to the working directory
+    info!("Checking the config...");
+    let config = match load_layers(&config_paths(), global.config.as_deref()) {
+        Ok(layers) => {
+            let origins: Vec<&str> = layers.iter().map(|layer| layer.origin.as_str()).collect();
+            report(
+                "config",
+                ErrorKind::Config,
+                Ok(if layers.len() == 1 {
+                    format!(
+                        "no config found, using the embedded remailer list (searched {})",
+                        searched_paths()
+                    )
+                } else {
+                    format!("layers {}", origins.join(", "))
+                }),
+            )?;
+            match verify_config(&layers, global) {
+                Ok(errors) if errors.is_empty() => report(
+                    "config signatures",
+                    ErrorKind::Config,
+                    Ok("the configs are authenticated".to_string()),
+                )?,
+                Ok(errors) => {
+                    for error in errors {
+                        report("config signatures", ErrorKind::Config, Err(error.into()))?;
+                    }
+                }
+                Err(err) => report("config signatures", ErrorKind::Config, Err(err))?,
+            }
+            match load_layered_config(&layers) {
+                Ok(config) => Some(config),
+                Err(err) => {
+                    report("config", ErrorKind::Config, Err(err))?;
+                    None
+                }
             }
         }
+        Err(err) => {
+            report("config", ErrorKind::Config, Err(err.into()))?;
+            None
+        }
+    };
+
+    // The key of each enabled remailer
+    if let Some(config) = config {
+        let mut valid = 0;
+        for remailer in config.remailers.iter().filter(|remailer| remailer.enable) {
+            match check_key(remailer) {
+                Ok(()) => valid += 1,
+                Err(err) => report(&remailer.email, ErrorKind::KeyImport, Err(err))?,
+            }
+        }
+        report(
+            "remailer keys",
+            ErrorKind::KeyImport,
+            Ok(format!("{} valid keys of enabled remailers", valid)),
+        )?;
     }
-    // If the produced chain is empty, we make an error!
-    if rchain.is_empty() {
-        eprintln!("No chain selected, the program will exit...");
-        println!("usage: To select a remailer chain, use `-c <remailer>`");
-        return Err(err_msg("No chain selected"));
+
+    if failures == 0 {
+        outln!("No problem found.");
+        Ok(())
+    } else {
+        let kind = first_kind.unwrap_or(ErrorKind::Config);
+        Err(err_msg(format!("{} problem(s) found!", failures))
+            .context(kind)
+            .into())
     }
-    Ok(rchain)
 }
 
-/// Print error, causes and backtrace from an error.
-fn print_errors(err: FError) {
-    println!();
-    eprintln!("Error occured: {}\n\ncauses:", err);
-    err.iter_chain().enumerate().for_each(|(index, fail)| {
-        eprintln!("\u{2001}{}: {}", index + 1, fail);
-    });
-    eprintln!("\n{}", err.backtrace())
+/// Check the layers of the config and print every problem found, with the layer it's in
+fn check_config_layers(global: &GlobalOpt) -> Fallible<()> {
+    let layers = load_layers(&config_paths(), global.config.as_deref())?;
+    print_layers(&layers);
+    let errors = verify_config(&layers, global).context(ErrorKind::Config)?;
+    for error in &errors {
+        outln!("  {}", error);
+    }
+    let problems = check_layers(&layers);
+    for (origin, error) in &problems {
+        outln!("  {}: {}", origin, error);
+    }
+    let found = errors.len() + problems.len();
+    if found == 0 {
+        outln!("The config is valid.");
+        Ok(())
+    } else {
+        Err(
+            err_msg(format!("{} problem(s) found in the config!", found))
+                .context(ErrorKind::Config)
+                .into(),
+        )
+    }
+}
+
+/// Upgrade a config file to the current schema version, keeping a copy of the old one
+fn migrate_config_file(path: &Path) -> Fallible<()> {
+    let mut config = read_config(path)?;
+    if config.get("version").is_none() {
+        info!("{:?} is an overlay, it doesn't need any migration.", path);
+        return Ok(());
+    }
+    let schema = schema_version(&config);
+    let applied = migrate(&mut config, &config_dir(path), &path.to_string_lossy())?;
+    if applied.is_empty() {
+        info!("{:?} is up to date (schema {}).", path, schema);
+        return Ok(());
+    }
+
+    // Keep the old config, then write the new one
+    let backup = write_config(path, &config).context(ErrorKind::Io)?;
+    info!(
+        "{:?} upgraded from schema {} to {} (old config in {:?}):",
+        path, schema, CURRENT_SCHEMA, backup
+    );
+    for description in applied {
+        info!("  {}", description);
+    }
+    warn_rewritten(path);
+    Ok(())
 }
 
-/// A representation for the JSON config needed.
-#[derive(Deserialize, Eq, PartialEq, Clone, Debug, Default)]
-struct RemailerConfig {
-    version: String,
-    authors: Vec<String>,
-    remailers: Vec<Remailer>,
+/// Merge a new remailer list into the local one, applying the changes accepted by the policy
+fn merge_config_file(
+    file: &Path,
+    into: &Option<PathBuf>,
+    policy: MergePolicy,
+    global: &GlobalOpt,
+) -> Fallible<()> {
+    // The new list gives keys: it must be authenticated like the configs, and is parsed from
+    // the bytes verified
+    let layers = [ConfigLayer::read(file)?];
+    if let Some(error) = verify_config(&layers, global)
+        .context(ErrorKind::Config)?
+        .into_iter()
+        .next()
+    {
+        return Err(error
+            .context("Refusing an unauthenticated remailer list, see `--insecure-config`")
+            .into());
+    }
+
+    let target = local_list(into, global)?;
+    let local = load_config(&target).context(ErrorKind::Config)?;
+    let new = load_layered_config(&layers).context(ErrorKind::Config)?;
+    outln!(
+        "Changes from {:?} (version {}) to {:?} (version {}):",
+        target,
+        local.version,
+        file,
+        new.version
+    );
+    review_changes(&target, local, new, policy, false)
 }
 
-/// A representation for a remailer value in the JSON config needed
-#[derive(Deserialize, Eq, PartialEq, Clone, Debug, Default)]
-struct Remailer {
-    name: Vec<String>,
-    email: String,
-    enable: bool,
-    key: String,
+/// Refresh the remailers of the local list with the replies of a mailbox to the `remailer-key`
+/// and `remailer-conf` requests
+fn refresh_remailers(
+    mailbox: &Path,
+    into: &Option<PathBuf>,
+    policy: MergePolicy,
+    global: &GlobalOpt,
+) -> Fallible<()> {
+    let target = local_list(into, global)?;
+    let local = load_config(&target).context(ErrorKind::Config)?;
+    let emails = read_mailbox(mailbox).context(ErrorKind::Io)?;
+    let (remailers, notes) = refresh(&local.remailers, &emails);
+    info!("{} emails read from {:?}.", emails.len(), mailbox);
+    for note in notes {
+        info!("  {}", note);
+    }
+    for remailer in &remailers {
+        if let Some(stats) = &remailer.stats {
+            info!(
+                "  {}: latency {}, uptime {}",
+                remailer.email, stats.latency, stats.uptime
+            );
+        }
+    }
+    let new = RemailerConfig {
+        remailers,
+        ..local.clone()
+    };
+    outln!("Changes to {:?}:", target);
+    review_changes(&target, local, new, policy, true)
 }
 
-impl Remailer {
-    /// Return if this remailer is enable in the config
-    fn is_enabled(&self) -> bool {
-        self.enable
+/// Write the `remailer-key` and `remailer-conf` requests to the remailers, as a Maildir if the
+/// output is a directory, as an mbox otherwise
+fn request_updates(
+    from: &Option<String>,
+    output: &Option<PathBuf>,
+    all: bool,
+    global: &GlobalOpt,
+) -> Fallible<()> {
+    let layers = load_layers(&config_paths(), global.config.as_deref())?;
+    let config = load_layered_config(&layers).context(ErrorKind::Config)?;
+    let remailers: Vec<Remailer> = config
+        .remailers
+        .into_iter()
+        .filter(|remailer| all || remailer.enable)
+        .collect();
+    let deliveries = requests(&remailers, from.as_deref());
+    match output {
+        Some(dir) if dir.is_dir() => {
+            let new = dir.join("new");
+            create_dir_all(&new)
+                .context(format!("Cannot create {:?}", new))
+                .context(ErrorKind::Io)?;
+            let time = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs();
+            for (index, delivery) in deliveries.iter().enumerate() {
+                let path = new.join(format!(
+                    "{}.{}_{}.cypherpunk-cli",
+                    time,
+                    std::process::id(),
+                    index
+                ));
+                std::fs::write(&path, delivery.to_string())
+                    .context(format!("Cannot write the email {:?}", path))
+                    .context(ErrorKind::Io)?;
+            }
+            info!(
+                "{} requests written in the Maildir {:?}.",
+                deliveries.len(),
+                dir
+            );
+        }
+        Some(path) => {
+            std::fs::write(path, to_mbox(&deliveries))
+                .context(format!("Cannot write the mbox {:?}", path))
+                .context(ErrorKind::Io)?;
+            info!(
+                "{} requests written in the mbox {:?}.",
+                deliveries.len(),
+                path
+            );
+        }
+        None => out!("{}", to_mbox(&deliveries)),
     }
+    Ok(())
+}
 
-    /// Return and decode the key of this remailer
-    fn as_key(&self) -> Fallible<Vec<u8>> {
-        Ok(base64::decode(self.key.split_at(7).1).context(format!(
-            "Can't decode the base64-encoded key for `{}`!",
-            self.name[0]
-        ))?)
+/// Return the local remailer list to update: the given one, or the last complete list of the
+/// config layers
+fn local_list(into: &Option<PathBuf>, global: &GlobalOpt) -> Fallible<PathBuf> {
+    match into {
+        Some(path) => Ok(path.clone()),
+        None => load_layers(&config_paths(), global.config.as_deref())?
+            .into_iter()
+            .rev()
+            .filter(ConfigLayer::is_complete)
+            .find_map(|layer| layer.path)
+            .ok_or_else(|| {
+                err_msg(format!(
+                    "No local remailer list found (searched {}), give one with `--into`",
+                    searched_paths()
+                ))
+            })
+            .context(ErrorKind::Usage)
+            .map_err(FError::from),
     }
 }
 
-/// Make a map of name-to-remailer from a list of remailers
-fn remailer_map(remailers: Vec<Remailer>) -> HashMap<String, String> {
-    let mut map = HashMap::new();
-    if remailers.is_empty() {
-        eprintln!("Without any remailer, the program will panic soon...")
+/// Import the remailers of a pinger's files, as a new list or into a local one
+fn import_pubring(files: &[PathBuf], into: &Option<PathBuf>, policy: MergePolicy) -> Fallible<()> {
+    let mut pubring = Pubring::default();
+    for path in files {
+        let text = std::fs::read_to_string(path)
+            .context(format!("File {:?} cannot be read!", path))
+            .context(ErrorKind::Io)?;
+        pubring
+            .read(&text)
+            .context(format!("Cannot read the pinger's file {:?}", path))
+            .context(ErrorKind::KeyImport)?;
+    }
+    let remailers = pubring.remailers().context(ErrorKind::KeyImport)?;
+    info!(
+        "{} remailer keys found, {} remailer-conf lines, {} type-II remailers.",
+        remailers.len(),
+        pubring.confs.len(),
+        pubring.type2.len()
+    );
+
+    // Update the local list, the imported keys being reviewed like a new list
+    if let Some(target) = into.as_ref().filter(|target| target.exists()) {
+        let local = load_config(target).context(ErrorKind::Config)?;
+        let new = RemailerConfig {
+            remailers: update_remailers(&local.remailers, &remailers),
+            ..local.clone()
+        };
+        outln!("Changes to {:?}:", target);
+        return review_changes(target, local, new, policy, false);
+    }
+
+    // Or write a new list
+    let config = serde_json::to_value(RemailerConfig {
+        schema: CURRENT_SCHEMA,
+        version: "imported".to_string(),
+        authors: Vec::new(),
+        remailers,
+    })?;
+    match into {
+        Some(target) => {
+            let format = ConfigFormat::detect(target, "{");
+            std::fs::write(target, format.serialize(&config)?)
+                .context(format!("Cannot write the config {:?}", target))
+                .context(ErrorKind::Io)?;
+            info!("New remailer list written in {:?}.", target);
+        }
+        None => out!("{}", ConfigFormat::Json.serialize(&config)?),
+    }
+    Ok(())
+}
+
+/// Report the changes from a local remailer list to a new one, then apply the ones accepted by
+/// the policy to the local list file. The changes made by the `replies` of the remailers are
+/// accepted as by `MergePolicy::accepts_reply`.
+fn review_changes(
+    target: &Path,
+    local: RemailerConfig,
+    new: RemailerConfig,
+    policy: MergePolicy,
+    replies: bool,
+) -> Fallible<()> {
+    let changes = diff(&local.remailers, &new.remailers);
+    if changes.is_empty() {
+        outln!("  none, the lists have the same remailers.");
+        return Ok(());
+    }
+    let mut accepted = Vec::new();
+    for change in &changes {
+        outln!("  {}", change);
+        let accepts = if replies {
+            policy.accepts_reply(change)
+        } else {
+            policy.accepts(change)
+        };
+        let accept = match accepts {
+            Some(accept) => accept,
+            None => ask("    Apply this change?")?,
+        };
+        if accept {
+            accepted.push(change.clone());
+        }
+    }
+    if policy == MergePolicy::Report {
+        return Ok(());
+    }
+
+    // The new list, without the refused changes; the key files of the new list are inlined
+    let mut remailers = apply(&local.remailers, &new.remailers, &accepted);
+    for remailer in &mut remailers {
+        let local_key = local
+            .remailers
+            .iter()
+            .any(|old| old.key == remailer.key && old.base_dir == remailer.base_dir);
+        if !local_key {
+            remailer
+                .inline_key()
+                .context(format!("Cannot inline the key of `{}`", remailer.email))
+                .context(ErrorKind::KeyImport)?;
+        }
+    }
+    let config = RemailerConfig { remailers, ..new };
+    let backup = write_config(target, &serde_json::to_value(&config)?).context(ErrorKind::Io)?;
+    info!(
+        "{} of {} change(s) applied to {:?} (old list in {:?}).",
+        accepted.len(),
+        changes.len(),
+        target,
+        backup
+    );
+    warn_rewritten(target);
+    Ok(())
+}
+
+/// Ask a yes/no question on the terminal, no being the default
+fn ask(question: &str) -> Fallible<bool> {
+    // On stderr, like the diagnostics, to keep stdout for the changes
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    stdin()
+        .read_line(&mut answer)
+        .context("Cannot read the answer")?;
+    let answer = answer.trim();
+    Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+}
+
+/// Warn about what is lost when a config file is rewritten: its signature and its comments
+fn warn_rewritten(path: &Path) {
+    let layer = ConfigLayer {
+        origin: path.to_string_lossy().into_owned(),
+        path: Some(path.to_path_buf()),
+        data: Vec::new(),
+        value: Value::Null,
+    };
+    if layer.signature_path().is_some() {
+        warn!("its signature is not valid anymore, sign it again!");
+    }
+    let format = std::fs::read_to_string(path).map(|text| ConfigFormat::detect(path, &text));
+    if format.is_ok_and(|format| format != ConfigFormat::Json) {
+        warn!("its comments are not kept, copy them from the old config!");
+    }
+}
+
+/// Verify the signatures of the config layers with the keys of the trusted list signers, unless
+/// `--insecure-config` is given; return the problems found
+fn verify_config(layers: &[ConfigLayer], global: &GlobalOpt) -> Fallible<Vec<ConfigError>> {
+    if global.insecure_config {
+        warn!("the config is not authenticated (--insecure-config)!");
+        return Ok(Vec::new());
+    }
+    // Only the embedded list, nothing to verify
+    if !layers
+        .iter()
+        .any(|layer| layer.path.is_some() && layer.sets_keys())
+    {
+        return Ok(Vec::new());
     }
-    // For each remailers
-    for remailer in remailers {
-        // We check if it is enabled
-        if remailer.enable {
-            // For each alias and email of this remailer, we add it to the map
-            map.insert(remailer.email.clone(), remailer.email.clone());
-            for alias in remailer.name {
-                map.insert(alias, remailer.email.clone());
+
+    // A throwaway home directory with only the keys of the trusted list signers: never the
+    // shared fallback keyring, which holds the remailers' keys imported before
+    let homedir = tempdir().context("Cannot create a temporary GnuPG home directory!")?;
+    let pgp = GPGBackend::with_homedir(homedir.path().to_path_buf(), None, global.quiet);
+    if pgp.uses_fallback_keyring() {
+        return Err(err_msg(
+            "Cannot verify the configs with the shared fallback keyring!",
+        ));
+    }
+    for path in signer_key_paths().iter().chain(&global.signers) {
+        pgp.import_key(read_file(path)?)
+            .context(format!("Cannot import the list signer key {:?}", path))?;
+    }
+    Ok(verify_layers(layers, &pgp))
+}
+
+/// Print the layers of the config, from the lowest precedence to the highest
+fn print_layers(layers: &[ConfigLayer]) {
+    let origins: Vec<String> = layers
+        .iter()
+        .map(|layer| {
+            let kind = if layer.is_complete() {
+                "list"
+            } else {
+                "overlay"
+            };
+            format!("{} ({})", layer.origin, kind)
+        })
+        .collect();
+    info!("Config layers: {}", origins.join(", "));
+}
+
+/// Return the config files searched, for messages
+fn searched_paths() -> String {
+    config_paths()
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Check that the key of a remailer decodes, and that one of its user IDs is its email
+fn check_key(remailer: &Remailer) -> Fallible<()> {
+    let key = remailer.as_key()?;
+    let user_ids = packet::user_ids(&key).context("Invalid OpenPGP key")?;
+    if user_ids
+        .iter()
+        .any(|user_id| packet::user_id_email(user_id).eq_ignore_ascii_case(&remailer.email))
+    {
+        Ok(())
+    } else {
+        Err(err_msg(format!(
+            "The key is for {}, not for this email",
+            user_ids.join(", ")
+        )))
+    }
+}
+
+/// Print the onion of a route: what each remailer reads when it decrypts its layer
+fn print_route(index: usize, route: &Route, message: &[u8]) -> Fallible<()> {
+    let (layers, size) = route.layers(message.len());
+    outln!("\nMessage n°{}: {} hops", index + 1, layers.len());
+    if let Some(first) = route.hops.first() {
+        outln!("  You send ≈{} bytes to {}", size, first.email);
+        for header in first.all_headers() {
+            outln!("      {}", header);
+        }
+    }
+    for (index, (layer, hop)) in layers.iter().zip(&route.hops).enumerate() {
+        let key = hop.fingerprint.as_deref().unwrap_or("unknown key");
+        outln!(
+            "  {}. {} ({}) decrypts ≈{} bytes and reads:",
+            index + 1,
+            layer.remailer,
+            key,
+            layer.size
+        );
+        match &layer.anon_to {
+            Some(anon_to) => {
+                outln!("      Anon-To: {}", anon_to);
+                for header in &layer.headers {
+                    outln!("      {}", header);
+                }
+            }
+            // The last remailer reads the headers of the message itself
+            None => {
+                let message = String::from_utf8_lossy(message);
+                match message.strip_prefix("::\n") {
+                    Some(headers) => {
+                        for header in headers.lines().take_while(|line| !line.is_empty()) {
+                            outln!("      {}", header);
+                        }
+                    }
+                    None => outln!("      (no `::` headers in your message!)"),
+                }
             }
         }
     }
-    map
+    Ok(())
+}
+
+/// Import remailers' key in the Cypherpunk core from a vec of remailer.
+/// It will only import enabled remailers, failing on a key which can't be decoded or doesn't
+/// match its pinned fingerprint.
+fn import_keys(core: &impl Cypherpunk, remailers: &[Remailer]) -> Fallible<()> {
+    // Retrieve enabled remailers' keys
+    let keys: Vec<Vec<u8>> = remailers
+        .iter()
+        .filter(|remailer| remailer.is_enabled())
+        .map(Remailer::as_key)
+        .collect::<Result<_, KeyError>>()?;
+
+    debug!("{} keys of enabled remailers to import", keys.len());
+
+    // Import keys in the Cypherpunk Core
+    Ok(core
+        .import_keys(keys)
+        .context("Failed to import keys in the Cypherpunk core!")?)
+}
+
+/// Return the causes of an error, on one line, without its kind
+fn causes(err: &FError) -> String {
+    let causes: Vec<String> = err
+        .iter_chain()
+        .filter(|fail| fail.downcast_ref::<Context<ErrorKind>>().is_none())
+        .map(ToString::to_string)
+        .collect();
+    causes.join(": ")
+}
+
+/// Print error, causes and backtrace from an error. The kind of the error, given by the exit
+/// code, is not printed: the innermost cause comes first.
+fn print_errors(err: FError) {
+    eprintln!("Error occured: {}\n\ncauses:", err.find_root_cause());
+    err.iter_chain()
+        .filter(|fail| fail.downcast_ref::<Context<ErrorKind>>().is_none())
+        .enumerate()
+        .for_each(|(index, fail)| {
+            eprintln!("\u{2001}{}: {}", index + 1, fail);
+        });
+    eprintln!("\n{}", err.backtrace())
 }
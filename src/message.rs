@@ -0,0 +1,127 @@
+use std::fmt;
+
+use failure::{err_msg, Fallible};
+
+/// A `Key: Value` header of a remailer message
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+impl Header {
+    /// Make a new header
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    /// Parse a `Key: Value` line
+    pub fn parse(line: &str) -> Fallible<Header> {
+        match line.split_once(':') {
+            Some((name, value)) if !name.trim().is_empty() && !name.contains(' ') => {
+                Ok(Header::new(name.trim(), value.trim()))
+            }
+            _ => Err(err_msg(format!(
+                "Invalid header `{}`, it must be a `Key: Value` pair!",
+                line
+            ))),
+        }
+    }
+
+    /// Return if this header has the given name (case insensitive)
+    pub fn is(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.value)
+    }
+}
+
+/// A Type-I remailer message: the `::` headers read by the remailer, the `##` headers added to
+/// the delivered email and the body
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct Message {
+    pub headers: Vec<Header>,
+    pub hash_headers: Vec<Header>,
+    pub body: String,
+}
+
+impl Message {
+    /// Make a message with `::` headers
+    pub fn new(headers: Vec<Header>, body: String) -> Self {
+        Self {
+            headers,
+            hash_headers: Vec::new(),
+            body,
+        }
+    }
+
+    /// Parse a message; without `::` block, all the text is the body
+    pub fn parse(text: &str) -> Fallible<Message> {
+        let text = text.replace("\r\n", "\n");
+        let text = text.trim_start_matches('\n');
+        let (headers, rest) = match text.strip_prefix("::\n") {
+            Some(rest) => parse_block(rest)?,
+            None => (Vec::new(), text),
+        };
+        let (hash_headers, body) = match rest.strip_prefix("##\n") {
+            Some(rest) => parse_block(rest)?,
+            None => (Vec::new(), rest),
+        };
+        Ok(Message {
+            headers,
+            hash_headers,
+            body: body.to_string(),
+        })
+    }
+
+    /// Return the value of the first `::` header with the given name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.is(name))
+            .map(|header| header.value.as_str())
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.headers.is_empty() {
+            writeln!(f, "::")?;
+            for header in &self.headers {
+                writeln!(f, "{}", header)?;
+            }
+            writeln!(f)?;
+        }
+        if !self.hash_headers.is_empty() {
+            writeln!(f, "##")?;
+            for header in &self.hash_headers {
+                writeln!(f, "{}", header)?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "{}", self.body)
+    }
+}
+
+/// Parse the header lines of a block until an empty line, return them with the remaining text
+fn parse_block(text: &str) -> Fallible<(Vec<Header>, &str)> {
+    if let Some(rest) = text.strip_prefix('\n') {
+        return Ok((Vec::new(), rest));
+    }
+    let (block, rest) = match text.find("\n\n") {
+        Some(end) => (&text[..end], &text[end + 2..]),
+        None => (text.trim_end_matches('\n'), ""),
+    };
+    let headers = block
+        .lines()
+        .map(Header::parse)
+        .collect::<Fallible<Vec<Header>>>()?;
+    Ok((headers, rest))
+}
@@ -0,0 +1,114 @@
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use crate::config::{ConfigError, Remailer};
+
+/// The schema version of the configs written by this version of cypherpunk-cli
+pub const CURRENT_SCHEMA: u64 = 2;
+
+/// A migration of a complete config from a schema version to the next one
+struct Migration {
+    from: u64,
+    description: &'static str,
+    migrate: fn(&mut Value, &Path),
+}
+
+/// The migrations, in order
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: 1,
+    description: "give the capabilities and the fingerprint of each remailer",
+    migrate: add_fingerprints,
+}];
+
+/// Return the schema version of a config, 1 without any
+pub fn schema_version(config: &Value) -> u64 {
+    config.get("schema").and_then(Value::as_u64).unwrap_or(1)
+}
+
+/// Refuse a config newer than this version of cypherpunk-cli. The older ones are read as they
+/// are: only `config migrate` upgrades them, pinning the fingerprints of the keys it finds.
+pub fn check_supported(config: &Value, file: &str) -> Result<(), ConfigError> {
+    let schema = schema_version(config);
+    if schema > CURRENT_SCHEMA {
+        return Err(ConfigError::TooNew {
+            file: file.to_string(),
+            schema,
+            supported: CURRENT_SCHEMA,
+        });
+    }
+    Ok(())
+}
+
+/// Upgrade a complete config to the current schema version (see `CURRENT_SCHEMA`), its key
+/// files being looked for in `base_dir`; return the description of each migration applied.
+///
+/// A config newer than this version of cypherpunk-cli is refused, as are the overlays (without
+/// `version`) which are never migrated: they only change the fields they give.
+pub fn migrate(
+    config: &mut Value,
+    base_dir: &Path,
+    file: &str,
+) -> Result<Vec<&'static str>, ConfigError> {
+    check_supported(config, file)?;
+    let schema = schema_version(config);
+    if config.get("version").is_none() {
+        return Ok(Vec::new());
+    }
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.from >= schema)
+    {
+        (migration.migrate)(config, base_dir);
+        applied.push(migration.description);
+    }
+
+    // The schema version goes first
+    if let Some(fields) = config.as_object_mut() {
+        let mut ordered = Map::new();
+        ordered.insert("schema".to_string(), Value::from(CURRENT_SCHEMA));
+        ordered.extend(
+            fields
+                .iter()
+                .filter(|(field, _)| field.as_str() != "schema")
+                .map(|(field, value)| (field.clone(), value.clone())),
+        );
+        *fields = ordered;
+    }
+    Ok(applied)
+}
+
+/// Schema 1 to 2: list the capabilities of each remailer (none known, all supposed), and pin
+/// the fingerprint of its key when it can be read
+fn add_fingerprints(config: &mut Value, base_dir: &Path) {
+    let remailers = match config.get_mut("remailers").and_then(Value::as_array_mut) {
+        Some(remailers) => remailers,
+        None => return,
+    };
+    for remailer in remailers.iter_mut() {
+        let fingerprint = serde_json::from_value::<Remailer>(remailer.clone())
+            .ok()
+            .and_then(|parsed| {
+                Remailer {
+                    base_dir: Some(base_dir.to_path_buf()),
+                    ..parsed
+                }
+                .fingerprint()
+                .ok()
+            });
+        let fields = match remailer.as_object_mut() {
+            Some(fields) => fields,
+            None => continue,
+        };
+        fields
+            .entry("capabilities")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Some(fingerprint) = fingerprint {
+            fields
+                .entry("fingerprint")
+                .or_insert_with(|| Value::String(fingerprint));
+        }
+    }
+}
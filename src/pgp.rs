@@ -1,23 +1,559 @@
+/// A decoder of ASCII-armored OpenPGP data
+pub mod armor {
+    use failure::{err_msg, Fallible, ResultExt};
+
+    /// Return if the data is ASCII-armored
+    pub fn is_armored(data: &[u8]) -> bool {
+        String::from_utf8_lossy(data).contains("-----BEGIN PGP ")
+    }
+
+    /// Decode all the ASCII-armored blocks of a text into binary OpenPGP data
+    pub fn decode(text: &str) -> Fallible<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut lines = text.lines().map(str::trim_end);
+        let mut found = false;
+        while let Some(line) = lines.next() {
+            if !line.starts_with("-----BEGIN PGP ") {
+                continue;
+            }
+            found = true;
+            // Skip the armor headers, until the empty line
+            for line in &mut lines {
+                if line.is_empty() {
+                    break;
+                }
+            }
+            // Then read the base64 data, until the checksum or the end of the block
+            let base64: String = (&mut lines)
+                .take_while(|line| !line.starts_with('=') && !line.starts_with("-----END PGP "))
+                .collect();
+            data.append(
+                &mut base64::decode(&base64).context("Invalid base64 data in the armored block")?,
+            );
+        }
+        if !found {
+            return Err(err_msg("No ASCII-armored OpenPGP block found"));
+        }
+        Ok(data)
+    }
+
+    /// Return the binary form of OpenPGP data, armored or not
+    pub fn dearmor(data: &[u8]) -> Fallible<Vec<u8>> {
+        if is_armored(data) {
+            decode(&String::from_utf8_lossy(data))
+        } else {
+            Ok(data.to_vec())
+        }
+    }
+}
+
+/// A minimal reader of binary OpenPGP data, just enough to identify the remailers' keys
+pub mod packet {
+    use failure::{err_msg, Fallible};
+    use sha1::Sha1;
+
+    /// Tag of a public key packet
+    pub const PUBLIC_KEY: u8 = 6;
+    /// Tag of a secret key packet
+    pub const SECRET_KEY: u8 = 5;
+    /// Tag of a user ID packet
+    pub const USER_ID: u8 = 13;
+    /// Tag of a signature packet
+    pub const SIGNATURE: u8 = 2;
+    /// Tag of a public subkey packet
+    pub const PUBLIC_SUBKEY: u8 = 14;
+    /// Tag of a secret subkey packet
+    pub const SECRET_SUBKEY: u8 = 7;
+
+    /// An OpenPGP packet: its tag and its body
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Packet<'a> {
+        pub tag: u8,
+        pub body: &'a [u8],
+    }
+
+    /// Split binary OpenPGP data into its packets
+    pub fn packets(mut data: &[u8]) -> Fallible<Vec<Packet<'_>>> {
+        let mut packets = Vec::new();
+        while !data.is_empty() {
+            let (packet, size) = next_packet(data)?;
+            packets.push(packet);
+            data = &data[size..];
+        }
+        Ok(packets)
+    }
+
+    /// Split a binary keyring into its keys, each one starting with its primary key packet
+    pub fn split_keys(keyring: &[u8]) -> Fallible<Vec<&[u8]>> {
+        let mut keys = Vec::new();
+        let (mut start, mut offset) = (None, 0);
+        while offset < keyring.len() {
+            let (packet, size) = next_packet(&keyring[offset..])?;
+            if packet.tag == PUBLIC_KEY || packet.tag == SECRET_KEY {
+                if let Some(start) = start {
+                    keys.push(&keyring[start..offset]);
+                }
+                start = Some(offset);
+            }
+            offset += size;
+        }
+        if let Some(start) = start {
+            keys.push(&keyring[start..]);
+        }
+        Ok(keys)
+    }
+
+    /// Read the first packet of binary OpenPGP data, return it with its size (header included)
+    fn next_packet(data: &[u8]) -> Fallible<(Packet<'_>, usize)> {
+        let ctb = data[0];
+        if ctb & 0x80 == 0 {
+            return Err(err_msg("Invalid OpenPGP data (bad packet header)"));
+        }
+        // Read the packet tag and the size of its length field
+        let (tag, header, len) = if ctb & 0x40 != 0 {
+            // New packet format
+            match data.get(1) {
+                Some(&len) if len < 192 => (ctb & 0x3f, 2, Some(len as usize)),
+                Some(&len) if len < 224 => (
+                    ctb & 0x3f,
+                    3,
+                    data.get(2)
+                        .map(|&len2| ((len as usize - 192) << 8) + len2 as usize + 192),
+                ),
+                Some(255) => (ctb & 0x3f, 6, read_be(data.get(2..6))),
+                _ => return Err(err_msg("Invalid OpenPGP data (unsupported packet length)")),
+            }
+        } else {
+            // Old packet format
+            let tag = (ctb >> 2) & 0x0f;
+            match ctb & 0x03 {
+                0 => (tag, 2, read_be(data.get(1..2))),
+                1 => (tag, 3, read_be(data.get(1..3))),
+                2 => (tag, 5, read_be(data.get(1..5))),
+                _ => (tag, 1, Some(data.len() - 1)),
+            }
+        };
+        let len = len.ok_or_else(|| err_msg("Invalid OpenPGP data (truncated header)"))?;
+        let body = data
+            .get(header..header + len)
+            .ok_or_else(|| err_msg("Invalid OpenPGP data (truncated packet)"))?;
+        Ok((Packet { tag, body }, header + len))
+    }
+
+    /// Read a big-endian number
+    fn read_be(bytes: Option<&[u8]>) -> Option<usize> {
+        bytes.map(|bytes| {
+            bytes
+                .iter()
+                .fold(0usize, |acc, &byte| (acc << 8) + byte as usize)
+        })
+    }
+
+    /// Return the fingerprint (upper-case hex) of the primary key in binary OpenPGP data
+    pub fn fingerprint(key: &[u8]) -> Fallible<String> {
+        let packets = packets(key)?;
+        let primary = packets
+            .iter()
+            .find(|packet| packet.tag == PUBLIC_KEY || packet.tag == SECRET_KEY)
+            .ok_or_else(|| err_msg("No key found in the OpenPGP data"))?;
+        match primary.body.first() {
+            Some(4) => {
+                // The fingerprint of a v4 key is computed from its public part only
+                let public = public_part(primary.body)?;
+                let mut sha1 = Sha1::new();
+                sha1.update(&[0x99, (public.len() >> 8) as u8, public.len() as u8]);
+                sha1.update(public);
+                Ok(sha1.digest().to_string().to_uppercase())
+            }
+            Some(version) => Err(err_msg(format!(
+                "Unsupported OpenPGP key version {}",
+                version
+            ))),
+            None => Err(err_msg("Invalid OpenPGP data (empty key)")),
+        }
+    }
+
+    /// Return the user IDs of binary OpenPGP data
+    pub fn user_ids(key: &[u8]) -> Fallible<Vec<String>> {
+        Ok(packets(key)?
+            .iter()
+            .filter(|packet| packet.tag == USER_ID)
+            .map(|packet| String::from_utf8_lossy(packet.body).into_owned())
+            .collect())
+    }
+
+    /// Return the expiry time (seconds since the epoch) of the primary key in binary OpenPGP
+    /// data, as given by its latest self-signature (not verified); none if it never expires
+    pub fn expiry(key: &[u8]) -> Fallible<Option<u64>> {
+        let packets = packets(key)?;
+        let primary = packets
+            .iter()
+            .find(|packet| packet.tag == PUBLIC_KEY || packet.tag == SECRET_KEY)
+            .ok_or_else(|| err_msg("No key found in the OpenPGP data"))?;
+        let created = match primary.body.first() {
+            Some(4) => read_be(primary.body.get(1..5))
+                .ok_or_else(|| err_msg("Invalid OpenPGP data (truncated key)"))?
+                as u64,
+            _ => return Err(err_msg("Unsupported OpenPGP key version")),
+        };
+        // The signatures of the primary key and its user IDs are before the subkeys
+        let mut latest: Option<(u64, Option<u64>)> = None;
+        for signature in packets
+            .iter()
+            .take_while(|packet| packet.tag != PUBLIC_SUBKEY && packet.tag != SECRET_SUBKEY)
+            .filter(|packet| packet.tag == SIGNATURE)
+        {
+            if let Some((time, expiration)) = certification(signature.body) {
+                let newer = match latest {
+                    Some((latest, _)) => time >= latest,
+                    None => true,
+                };
+                if newer {
+                    latest = Some((time, expiration));
+                }
+            }
+        }
+        Ok(latest
+            .and_then(|(_, expiration)| expiration)
+            .filter(|&expiration| expiration != 0)
+            .map(|expiration| created + expiration))
+    }
+
+    /// Read a v4 certification signature: its creation time and the key expiration time it
+    /// gives (after the key creation), from its hashed subpackets
+    fn certification(body: &[u8]) -> Option<(u64, Option<u64>)> {
+        // Version 4, and a certification (of a user ID, or of the key itself)
+        if body.first() != Some(&4) || !matches!(body.get(1), Some(0x10..=0x13) | Some(0x1f)) {
+            return None;
+        }
+        let len = read_be(body.get(4..6))?;
+        let mut subpackets = body.get(6..6 + len)?;
+        let (mut time, mut expiration) = (0, None);
+        while let Some(&first) = subpackets.first() {
+            let (len, header) = match first {
+                0..=191 => (first as usize, 1),
+                192..=254 => (
+                    ((first as usize - 192) << 8) + *subpackets.get(1)? as usize + 192,
+                    2,
+                ),
+                255 => (read_be(subpackets.get(1..5))?, 5),
+            };
+            let (kind, data) = subpackets.get(header..header + len)?.split_first()?;
+            // The high bit of the type is the critical flag
+            match kind & 0x7f {
+                2 => time = read_be(data.get(..4))? as u64,
+                9 => expiration = Some(read_be(data.get(..4))? as u64),
+                _ => {}
+            }
+            subpackets = &subpackets[header + len..];
+        }
+        Some((time, expiration))
+    }
+
+    /// Return the email of a user ID: `Name <email>` or `email`
+    pub fn user_id_email(user_id: &str) -> &str {
+        match (user_id.rfind('<'), user_id.rfind('>')) {
+            (Some(start), Some(end)) if start < end => &user_id[start + 1..end],
+            _ => user_id.trim(),
+        }
+    }
+
+    /// Return the public part of a v4 key packet body: version, time, algorithm and public MPIs
+    fn public_part(body: &[u8]) -> Fallible<&[u8]> {
+        let mpis = match body.get(5) {
+            // RSA
+            Some(1) | Some(2) | Some(3) => 2,
+            // ElGamal
+            Some(16) | Some(20) => 3,
+            // DSA
+            Some(17) => 4,
+            // ECDH: curve OID, point and KDF parameters
+            Some(18) => return ecc_end(body, 2).map(|end| &body[..end]),
+            // ECDSA and EdDSA: curve OID and point
+            Some(19) | Some(22) => return ecc_end(body, 1).map(|end| &body[..end]),
+            _ => return Err(err_msg("Unsupported OpenPGP public key algorithm")),
+        };
+        let mut end = 6;
+        for _ in 0..mpis {
+            end = mpi_end(body, end)?;
+        }
+        Ok(&body[..end])
+    }
+
+    /// Return the end of an elliptic curve public key, after `fields` fields following the OID
+    fn ecc_end(body: &[u8], fields: usize) -> Fallible<usize> {
+        let oid_len = *body
+            .get(6)
+            .ok_or_else(|| err_msg("Invalid OpenPGP data (truncated key)"))?
+            as usize;
+        let mut end = mpi_end(body, 7 + oid_len)?;
+        if fields == 2 {
+            // KDF parameters: a length-prefixed field
+            let kdf_len = *body
+                .get(end)
+                .ok_or_else(|| err_msg("Invalid OpenPGP data (truncated key)"))?
+                as usize;
+            end += 1 + kdf_len;
+        }
+        if end > body.len() {
+            return Err(err_msg("Invalid OpenPGP data (truncated key)"));
+        }
+        Ok(end)
+    }
+
+    /// Return the end of the MPI starting at `start`
+    fn mpi_end(body: &[u8], start: usize) -> Fallible<usize> {
+        let bits = read_be(body.get(start..start + 2))
+            .ok_or_else(|| err_msg("Invalid OpenPGP data (truncated key)"))?;
+        let end = start + 2 + bits.div_ceil(8);
+        if end > body.len() {
+            return Err(err_msg("Invalid OpenPGP data (truncated key)"));
+        }
+        Ok(end)
+    }
+}
+
+/// A fake PGP backend for the tests: its reversible "encryption" tags the recipient of each
+/// message, and it records its calls
+#[cfg(feature = "test-backend")]
+pub mod mock {
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use std::rc::Rc;
+
+    use failure::{err_msg, Fallible, ResultExt};
+    use sha1::Sha1;
+
+    use super::{armor, packet};
+    use crate::PGPBackend;
+
+    /// Prefix of the fake public keys
+    const KEY_PREFIX: &str = "MOCK PUBLIC KEY ";
+    /// Armor header giving the recipient of a fake encrypted message
+    const RECIPIENT: &str = "Mock-Recipient: ";
+    /// Prefix of the fake signatures
+    const SIGNATURE_PREFIX: &str = "MOCK SIGNATURE ";
+
+    /// A call to the PGP backend
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum Call {
+        ImportKey(Vec<u8>),
+        Encrypt {
+            recipients: Vec<String>,
+            input: Vec<u8>,
+        },
+    }
+
+    /// The state of a MockBackend, shared between its clones
+    #[derive(Debug, Default)]
+    struct State {
+        /// Emails of the imported keys
+        public: Vec<String>,
+        /// Emails of the generated key pairs
+        secret: Vec<String>,
+        calls: Vec<Call>,
+    }
+
+    /// A fake PGP backend. Its clones share the same keys and calls, so that a test can
+    /// check the calls of a backend given to a CypherpunkCore.
+    #[derive(Clone, Debug, Default)]
+    pub struct MockBackend {
+        state: Rc<RefCell<State>>,
+    }
+
+    impl MockBackend {
+        /// Create a new MockBackend instance, without any key
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Return all the recorded calls to `import_key` and `encrypt`
+        pub fn calls(&self) -> Vec<Call> {
+            self.state.borrow().calls.clone()
+        }
+
+        /// Return the recipient of a fake encrypted message
+        pub fn recipient(message: &str) -> Option<&str> {
+            message
+                .lines()
+                .find_map(|line| line.strip_prefix(RECIPIENT))
+        }
+
+        /// Return the fake public key of an email
+        pub fn public_key(email: &str) -> Vec<u8> {
+            format!("{}{}", KEY_PREFIX, email).into_bytes()
+        }
+
+        /// Return a fake detached signature of some data by the key of an email
+        pub fn sign(email: &str, data: &[u8]) -> Vec<u8> {
+            format!(
+                "{}{} {}",
+                SIGNATURE_PREFIX,
+                email,
+                Sha1::from(data).digest()
+            )
+            .into_bytes()
+        }
+    }
+
+    /// Return the email of a fake key, or of the first user ID of an OpenPGP key
+    fn key_email(key: &[u8]) -> Fallible<String> {
+        if let Some(email) = String::from_utf8_lossy(key).strip_prefix(KEY_PREFIX) {
+            return Ok(email.trim().to_lowercase());
+        }
+        let user_ids = packet::user_ids(&armor::dearmor(key)?)?;
+        user_ids
+            .first()
+            .map(|user_id| packet::user_id_email(user_id).to_lowercase())
+            .ok_or_else(|| err_msg("The key has no user ID"))
+    }
+
+    impl PGPBackend for MockBackend {
+        fn import_key(&self, key: Vec<u8>) -> Fallible<()> {
+            let mut state = self.state.borrow_mut();
+            state.calls.push(Call::ImportKey(key.clone()));
+            let email = key_email(&key).context("Cannot read the imported key")?;
+            state.public.push(email);
+            Ok(())
+        }
+
+        fn encrypt(
+            &self,
+            input: &mut dyn Read,
+            output: &mut dyn Write,
+            recipients: Vec<String>,
+        ) -> Fallible<()> {
+            let mut data = Vec::new();
+            input.read_to_end(&mut data)?;
+            let mut state = self.state.borrow_mut();
+            state.calls.push(Call::Encrypt {
+                recipients: recipients.clone(),
+                input: data.clone(),
+            });
+            for recipient in &recipients {
+                if !state.public.contains(&recipient.to_lowercase()) {
+                    return Err(err_msg(format!("No public key for `{}`", recipient)));
+                }
+            }
+            write!(
+                output,
+                "-----BEGIN PGP MESSAGE-----\n{}{}\n\n{}\n-----END PGP MESSAGE-----\n",
+                RECIPIENT,
+                recipients.join(", "),
+                base64::encode(&data)
+            )?;
+            Ok(())
+        }
+
+        fn generate_key(&self, user_id: &str) -> Fallible<Vec<u8>> {
+            let email = packet::user_id_email(user_id).to_lowercase();
+            let mut state = self.state.borrow_mut();
+            state.secret.push(email.clone());
+            state.public.push(email.clone());
+            Ok(Self::public_key(&email))
+        }
+
+        fn decrypt(&self, input: &mut dyn Read, output: &mut dyn Write) -> Fallible<()> {
+            let mut message = String::new();
+            input.read_to_string(&mut message)?;
+            let recipient = Self::recipient(&message)
+                .ok_or_else(|| err_msg("Not a mock encrypted message"))?
+                .to_lowercase();
+            if !self.state.borrow().secret.contains(&recipient) {
+                return Err(err_msg(format!(
+                    "The message is encrypted for `{}`, not for this key",
+                    recipient
+                )));
+            }
+            let data: String = message
+                .lines()
+                .skip_while(|line| !line.is_empty())
+                .take_while(|line| !line.starts_with("-----END"))
+                .collect();
+            output.write_all(&base64::decode(&data).context("Invalid mock encrypted message")?)?;
+            Ok(())
+        }
+
+        fn verify(&self, data: &[u8], signature: &[u8]) -> Fallible<()> {
+            let signature = String::from_utf8_lossy(signature);
+            let (email, digest) = signature
+                .strip_prefix(SIGNATURE_PREFIX)
+                .and_then(|signature| signature.trim().split_once(' '))
+                .ok_or_else(|| err_msg("Not a mock signature"))?;
+            if !self.state.borrow().public.contains(&email.to_lowercase()) {
+                return Err(err_msg(format!("No public key for `{}`", email)));
+            }
+            if digest != Sha1::from(data).digest().to_string() {
+                return Err(err_msg("Bad signature"));
+            }
+            Ok(())
+        }
+    }
+}
+
 #[cfg(feature = "back-gpg")]
 pub mod gpg {
     use std::env::temp_dir;
+    use std::ffi::{OsStr, OsString};
     use std::fs::File;
     use std::io;
     use std::io::{Read, Write};
-    use std::path::PathBuf;
-    use std::process::{Child, Command};
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+    use std::sync::Arc;
 
     use failure::{err_msg, Fallible, ResultExt};
-    use tempfile;
-    use tempfile::tempdir_in;
+    use tempfile::{tempdir_in, TempDir};
+
+    use crate::PGPBackend;
+
+    /// The oldest GnuPG version able to generate keys in its own home directory
+    pub const MIN_VERSION: (u32, u32) = (2, 1);
+
+    /// Return the first line of `gpg --version`, failing if gpg is not on the PATH
+    pub fn version() -> Fallible<String> {
+        let output = Command::new("gpg")
+            .arg("--version")
+            .output()
+            .context("Cannot run gpg, is GnuPG installed and on the PATH?")?;
+        if !output.status.success() {
+            return Err(err_msg("`gpg --version` failed"));
+        }
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .map(|line| line.trim().to_string())
+            .ok_or_else(|| err_msg("`gpg --version` printed nothing"))
+    }
 
-    use crate::lib::PGPBackend;
+    /// Check that a `gpg --version` line is from a supported GnuPG, return its version number
+    pub fn check_version(line: &str) -> Fallible<String> {
+        let number = line
+            .split_whitespace()
+            .last()
+            .ok_or_else(|| err_msg("Empty GnuPG version"))?;
+        let mut parts = number.split('.').map(|part| part.parse::<u32>().ok());
+        match (parts.next().flatten(), parts.next().flatten()) {
+            (Some(major), Some(minor)) if (major, minor) >= MIN_VERSION => Ok(number.to_string()),
+            (Some(_), Some(_)) => Err(err_msg(format!(
+                "GnuPG {} is too old, {}.{} or newer is needed",
+                number, MIN_VERSION.0, MIN_VERSION.1
+            ))),
+            _ => Err(err_msg(format!("Unknown GnuPG version `{}`", line))),
+        }
+    }
+
+    /// The keyring file used, in the temporary directory, when no temporary keyring can be made
+    pub const FALLBACK_KEYRING: &str = "cypherpunk-cli_keyring.gpg";
 
     /// A PGP Backend using the command-line gpg
-    #[derive(Clone, Debug, PartialEq, Eq)]
+    #[derive(Clone, Debug)]
     pub struct GPGBackend {
         temp_dir: PathBuf,
         keyring: PathBuf,
+        // The directory of the keyring, deleted when the last clone of the backend is dropped
+        _keyring_dir: Option<Arc<TempDir>>,
+        homedir: Option<PathBuf>,
         quiet: bool,
     }
 
@@ -26,11 +562,11 @@ pub mod gpg {
         pub fn new(temp: Option<PathBuf>, quiet: bool) -> Self {
             // Create a keyring file
             let tmp = temp.unwrap_or_else(temp_dir);
-            let keyring = match tempdir_in(tmp.clone()) {
-                Ok(dir) => dir.into_path().join("keyring.gpg"),
+            let (keyring, keyring_dir) = match tempdir_in(tmp.clone()) {
+                Ok(dir) => (dir.path().join("keyring.gpg"), Some(Arc::new(dir))),
                 Err(_err) => {
                     // "Keyring temp dir is unwrittable... Switch to fallback keyring;
-                    tmp.clone().join("cypherpunk-cli_keyring.gpg")
+                    (tmp.clone().join(FALLBACK_KEYRING), None)
                 }
             };
             // Return the GPGBackend
@@ -38,7 +574,106 @@ pub mod gpg {
                 quiet,
                 temp_dir: tmp,
                 keyring,
+                _keyring_dir: keyring_dir,
+                homedir: None,
+            }
+        }
+
+        /// Create a new GPGBackend instance with its own GnuPG home directory instead of a
+        /// keyring file, needed to use secret keys. The home directory is not deleted with the
+        /// backend: keep its `TempDir` as long as the backend if it's a temporary one.
+        pub fn with_homedir(homedir: PathBuf, temp: Option<PathBuf>, quiet: bool) -> Self {
+            // GnuPG warns about home directories readable by others
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let _ = std::fs::set_permissions(&homedir, std::fs::Permissions::from_mode(0o700));
+            }
+            Self {
+                temp_dir: temp.unwrap_or_else(temp_dir),
+                keyring: PathBuf::new(),
+                _keyring_dir: None,
+                homedir: Some(homedir),
+                quiet,
+            }
+        }
+
+        /// Return the temporary directory of the backend
+        pub fn temp_dir(&self) -> &Path {
+            &self.temp_dir
+        }
+
+        /// Return if the temporary directory was unwritable, and the keys go to the fallback
+        /// keyring shared by every run
+        pub fn uses_fallback_keyring(&self) -> bool {
+            self.homedir.is_none() && self.keyring == self.temp_dir.join(FALLBACK_KEYRING)
+        }
+
+        /// Run gpg on its keyring with the given arguments, each one passed as is (no shell)
+        fn run(&self, args: &[&OsStr]) -> Fallible<()> {
+            // Keyring (or home directory) and quiet flag
+            let mut command = Command::new("gpg");
+            match &self.homedir {
+                Some(homedir) => command.arg("--homedir").arg(homedir).arg("--batch"),
+                None => {
+                    let mut keyring = OsString::from("--keyring=");
+                    keyring.push(&self.keyring);
+                    command.arg("--no-default-keyring").arg(keyring)
+                }
+            };
+            if self.quiet {
+                command.arg("-q");
+            }
+            command.args(args);
+            log::trace!("Running {:?}", command);
+
+            // Run it and retrieve its exit status
+            let exit_state = command
+                .spawn()
+                .context("Failed to execute GPG")?
+                .wait()
+                .context("GPG unexpected exit")?;
+
+            // Check the result
+            match exit_state.code() {
+                Some(0) => Ok(()),
+                None => Err(err_msg("GPG exited without any exit code!")),
+                Some(other) => Err(err_msg(format!("GPG exited with code {}\nPlease check the GPG output for more information about the error.", other))),
+            }
+        }
+
+        /// Run gpg with an input file made of the given input, and an output file whose
+        /// content is copied to the given output
+        fn run_io(
+            &self,
+            input: &mut dyn Read,
+            output: &mut dyn Write,
+            args: &[&OsStr],
+        ) -> Fallible<()> {
+            // Creat a temp dir for input and output files, deleted with the message when dropped
+            let tmp_dir = tempdir_in(self.temp_dir.clone())
+                .context("Cannot create a temporary directory to encrypt your message!")?;
+
+            let in_path: PathBuf = tmp_dir.path().join("input.txt");
+            {
+                let mut tmp = File::create(in_path.clone()).context(
+                    "Cannot create an input file (which contains your message) to encrypt it",
+                )?;
+                io::copy(input, &mut tmp)
+                    .context("Cannot copy your message in temporary input file")?;
+                tmp.flush().context("Cannot save temporary message file")?;
             }
+
+            let out_path: PathBuf = tmp_dir.path().join("output.txt");
+            let files = [OsStr::new("-o"), out_path.as_os_str(), in_path.as_os_str()];
+            self.run(&[args, &files].concat())?;
+
+            // Open output file and copy data from it to the given output
+            let mut output_file =
+                File::open(&out_path).context("Cannot open your temporary encrypted message")?;
+            io::copy(&mut output_file, output)
+                .context("Cannot copy your encrypted message from temp file")?;
+            Ok(())
         }
     }
 
@@ -50,16 +685,11 @@ pub mod gpg {
 
     impl PGPBackend for GPGBackend {
         fn import_key(&self, key: Vec<u8>) -> Fallible<()> {
-            // Retrieve keyring path and quiet flag
-            let quiet = if self.quiet { "-q" } else { "" };
-            let keyring = &self.keyring;
-
-            // Create a temp dir and file who contain the key
-            let tmp_path = tempdir_in(self.temp_dir.clone())
-                .context("Cannot create a temporary directory to import the key!")?
-                .into_path();
+            // Create a temp dir and file who contain the key, deleted when dropped
+            let tmp_dir = tempdir_in(self.temp_dir.clone())
+                .context("Cannot create a temporary directory to import the key!")?;
 
-            let key_path: PathBuf = tmp_path.join("key.txt");
+            let key_path: PathBuf = tmp_dir.path().join("key.txt");
             {
                 let mut tmp =
                     File::create(key_path.clone()).context("Cannot create key file to import")?;
@@ -69,45 +699,11 @@ pub mod gpg {
             }
 
             // Import key from gpg command-line
-            let mut child = if cfg!(target_os = "windows") {
-                Command::new("cmd")
-                    .args(&[
-                        "/C",
-                        format!(
-                            "gpg --no-default-keyring --keyring={} --import --yes {} {}",
-                            keyring.to_string_lossy(),
-                            quiet,
-                            key_path.to_string_lossy()
-                        )
-                        .as_str(),
-                    ])
-                    .spawn()
-                    .context("Failed to execute GPG")?
-            } else {
-                Command::new("sh")
-                    .arg("-c")
-                    .arg(
-                        format!(
-                            "gpg --no-default-keyring --keyring={} --import --yes {} {}",
-                            keyring.to_string_lossy(),
-                            quiet,
-                            key_path.to_string_lossy()
-                        )
-                        .as_str(),
-                    )
-                    .spawn()
-                    .context("Failed to execute GPG")?
-            };
-
-            // retrieve its exit status
-            let exit_state = child.wait().context("GPG unexpected exit")?;
-
-            // Check importation result
-            match exit_state.code().unwrap_or(-1099) {
-                0 => Ok(()),
-                -1099 => Err(err_msg("GPG exited without any exit code!")),
-                other => Err(err_msg(format!("GPG exited with code {}\nPlease check the GPG output for more information about the error.", other))),
-            }
+            self.run(&[
+                OsStr::new("--import"),
+                OsStr::new("--yes"),
+                key_path.as_os_str(),
+            ])
         }
 
         fn encrypt(
@@ -116,74 +712,79 @@ pub mod gpg {
             output: &mut dyn Write,
             recipients: Vec<String>,
         ) -> Fallible<()> {
-            // Retrieve keyring path and quiet flag
-            let quiet = if self.quiet { "-q" } else { "" };
-            let keyring = &self.keyring;
+            // Create the recipients args
+            let mut args = Vec::new();
+            for recipient in &recipients {
+                args.push(OsStr::new("-r"));
+                args.push(OsStr::new(recipient));
+            }
+            args.extend(["-a", "--always-trust", "-e"].iter().map(OsStr::new));
 
-            // Creat a temp dir for input and output files
-            let tmp_path = tempdir_in(self.temp_dir.clone())
-                .context("Cannot create a temporary directory to encrypt your message!")?
-                .into_path();
+            // Run encryption from gpg command-line
+            self.run_io(input, output, &args)
+        }
 
-            let in_path: PathBuf = tmp_path.clone().join("input.txt");
-            {
-                let mut tmp = File::create(in_path.clone()).context(
-                    "Cannot create an input file (which contains your message) to encrypt it",
-                )?;
-                io::copy(input, &mut tmp)
-                    .context("Cannot copy your message in temporary input file")?;
-                tmp.flush().context("Cannot save temporary message file")?;
+        fn generate_key(&self, user_id: &str) -> Fallible<Vec<u8>> {
+            // The secret key must not go in the default GnuPG home directory
+            if self.homedir.is_none() {
+                return Err(err_msg(
+                    "GPG needs its own home directory to generate keys!",
+                ));
             }
+            self.run(&[
+                OsStr::new("--passphrase"),
+                OsStr::new(""),
+                OsStr::new("--quick-gen-key"),
+                OsStr::new(user_id),
+                OsStr::new("future-default"),
+                OsStr::new("default"),
+                OsStr::new("never"),
+            ])
+            .context("Cannot generate the key pair")?;
 
-            let out_path: PathBuf = tmp_path.join("output.txt");
-            // Create the recipients args
-            let recipients = recipients.join(" -r ");
-
-            // Run encryption from gpg command-line
-            let mut child: Child = if cfg!(target_os = "windows") {
-                Command::new("cmd")
-                    .arg("/C")
-                    .arg(format!(
-                        r#"gpg --no-default-keyring --keyring={} -r "{}" -a -o {} {} --always-trust -e {}"#,
-                        keyring.to_string_lossy(),
-                        recipients,
-                        out_path.to_string_lossy(),
-                        quiet,
-                        in_path.to_string_lossy()
-                    ))
-                    .spawn()
-                    .context("Failed to execute GPG")?
-            } else {
-                Command::new("sh")
-                    .arg("-c")
-                    .arg(format!(
-                        r#"gpg --no-default-keyring --keyring={} -r "{}" -a -o {} {} --always-trust -e {}"#,
-                        keyring.to_string_lossy(),
-                        recipients,
-                        out_path.to_string_lossy(),
-                        quiet,
-                        in_path.to_string_lossy()
-                    ))
-                    .spawn()
-                    .context("Failed to execute GPG")?
-            };
-            // retrieve its exit status
-            let exit_state = child.wait().context("GPG unexpected exit")?;
+            // Export the public key
+            let tmp_dir = tempdir_in(self.temp_dir.clone())
+                .context("Cannot create a temporary directory to export the key!")?;
+            let key_path: PathBuf = tmp_dir.path().join("key.gpg");
+            self.run(&[
+                OsStr::new("-o"),
+                key_path.as_os_str(),
+                OsStr::new("--export"),
+                OsStr::new(user_id),
+            ])
+            .context("Cannot export the public key")?;
+            let mut key = Vec::new();
+            File::open(&key_path)
+                .and_then(|mut file| file.read_to_end(&mut key))
+                .context("Cannot read the exported public key")?;
+            Ok(key)
+        }
 
-            // Check encryption result
-            match exit_state.code().unwrap_or(9999) {
-                0 => { // Success
+        fn decrypt(&self, input: &mut dyn Read, output: &mut dyn Write) -> Fallible<()> {
+            // Run decryption from gpg command-line
+            self.run_io(input, output, &[OsStr::new("--yes"), OsStr::new("-d")])
+        }
 
-                    // Open output file and copy data from it to the given output
-                    let mut output_file = File::open(&out_path).context("Cannot open your temporary encrypted message")?;
-                    io::copy(&mut output_file, output).context("Cannot copy your encrypted message from temp file")?;
+        fn verify(&self, data: &[u8], signature: &[u8]) -> Fallible<()> {
+            // Write the data and its signature in temp files
+            let tmp_dir = tempdir_in(self.temp_dir.clone())
+                .context("Cannot create a temporary directory to verify the signature!")?;
+            let data_path = tmp_dir.path().join("data");
+            let signature_path = tmp_dir.path().join("data.sig");
+            std::fs::write(&data_path, data).context("Cannot write the signed data file")?;
+            std::fs::write(&signature_path, signature)
+                .context("Cannot write the signature file")?;
 
-                    Ok(())
-                }
-                // Send error if the execution failed
-                9999 => Err(err_msg("GPG exited without any exit code!")),
-                other => Err(err_msg(format!("GPG exited with code {}\nPlease check the GPG output for more information about the error.", other))),
-            }
+            // Verify it from gpg command-line
+            self.run(&[
+                OsStr::new("--trust-model"),
+                OsStr::new("always"),
+                OsStr::new("--verify"),
+                signature_path.as_os_str(),
+                data_path.as_os_str(),
+            ])
+            .context("Bad signature, or not made by a trusted key")?;
+            Ok(())
         }
     }
 }
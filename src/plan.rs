@@ -0,0 +1,298 @@
+use std::fs::File;
+use std::path::Path;
+
+use failure::{err_msg, Fallible, ResultExt};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::Remailer;
+use crate::message::Header;
+
+/// The header used by remailers to delay a message
+pub const LATENT_TIME: &str = "Latent-Time";
+
+/// The known remailer headers, with the capability needed by a remailer to honour them
+const KNOWN_HEADERS: &[(&str, Option<&str>)] = &[
+    ("Anon-To", None),
+    ("Request-Remailing-To", None),
+    ("Remail-To", None),
+    ("Null", None),
+    ("Encrypted", Some("pgp")),
+    (LATENT_TIME, Some("latent")),
+    ("Encrypt-Key", Some("ek")),
+    ("Encrypt-Subject", Some("esub")),
+    ("Inflate", Some("inflt")),
+    ("Cutmarks", Some("cut")),
+    ("Anon-Post-To", Some("post")),
+    ("Post-To", Some("post")),
+    ("Remix-To", Some("remix")),
+];
+
+/// Estimated size added by the PGP encryption (session key, packets headers and MDC)
+const PGP_OVERHEAD: usize = 400;
+
+/// A routing plan: the resolved route of each redundancy copy of a message
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug, Default)]
+pub struct Plan {
+    pub copies: Vec<Route>,
+}
+
+/// The route of one message, from the first remailer to the last one
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug, Default)]
+pub struct Route {
+    pub hops: Vec<Hop>,
+}
+
+/// A remailer of a route. Its headers, and its delay as a `Latent-Time` header, are written
+/// next to its `Anon-To` header: the previous remailer of the route reads them.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug, Default)]
+pub struct Hop {
+    pub email: String,
+    pub fingerprint: Option<String>,
+    #[serde(default)]
+    pub headers: Vec<String>,
+    #[serde(default)]
+    pub delay: Option<String>,
+}
+
+impl Plan {
+    /// Read a plan from a JSON file
+    pub fn load<P: AsRef<Path>>(path: P) -> Fallible<Plan> {
+        Ok(serde_json::from_reader(
+            File::open(path.as_ref()).context(format!("Failed to open {:?}!", path.as_ref()))?,
+        )
+        .context(format!(
+            "Failed to parse {:?} as a json plan!",
+            path.as_ref()
+        ))?)
+    }
+
+    /// Write this plan into a JSON file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Fallible<()> {
+        Ok(serde_json::to_writer_pretty(
+            File::create(path.as_ref())
+                .context(format!("Cannot create file {:?}", path.as_ref()))?,
+            self,
+        )
+        .context(format!("Cannot write the plan in {:?}", path.as_ref()))?)
+    }
+
+    /// Check that each remailer of this plan is still enabled in the config with the same key
+    pub fn check(&self, remailers: &[Remailer]) -> Fallible<()> {
+        for (index, route) in self.copies.iter().enumerate() {
+            if route.hops.is_empty() {
+                return Err(err_msg(format!(
+                    "The route of message n°{} is empty!",
+                    index + 1
+                )));
+            }
+            for hop in &route.hops {
+                let remailer = remailers
+                    .iter()
+                    .find(|remailer| remailer.email == hop.email && remailer.is_enabled())
+                    .ok_or_else(|| {
+                        err_msg(format!(
+                            "Remailer `{}` of message n°{} is unknown or disabled in the config!",
+                            hop.email,
+                            index + 1
+                        ))
+                    })?;
+                if let Some(fingerprint) = &hop.fingerprint {
+                    let actual = remailer.fingerprint()?;
+                    if !actual.eq_ignore_ascii_case(fingerprint) {
+                        return Err(err_msg(format!(
+                            "The key of `{}` changed since the plan was made ({} in the plan, {} in the config)!",
+                            hop.email, fingerprint, actual
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A layer of the onion, as the remailer which decrypts it sees it
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Layer {
+    /// The remailer which decrypts this layer
+    pub remailer: String,
+    /// The next remailer, none for the last remailer which delivers the message
+    pub anon_to: Option<String>,
+    /// The headers read by the remailer with the `Anon-To`
+    pub headers: Vec<String>,
+    /// Estimated size of the encrypted layer, without compression
+    pub size: usize,
+}
+
+impl Route {
+    /// Make the route of a chain of remailers' emails, each hop with the given headers.
+    /// A `Latent-Time` header becomes the delay of the hops.
+    pub fn new(chain: &[String], headers: &[String], remailers: &[Remailer]) -> Fallible<Route> {
+        // Take the delay out of the headers
+        let (delays, headers): (Vec<&String>, Vec<&String>) = headers.iter().partition(|header| {
+            Header::parse(header)
+                .map(|header| header.is(LATENT_TIME))
+                .unwrap_or(false)
+        });
+        let delay = delays
+            .last()
+            .map(|header| Header::parse(header))
+            .transpose()?
+            .map(|header| header.value);
+        let headers: Vec<String> = headers.into_iter().cloned().collect();
+
+        let hops = chain
+            .iter()
+            .map(|email| {
+                let remailer = remailers
+                    .iter()
+                    .find(|remailer| &remailer.email == email)
+                    .ok_or_else(|| {
+                        err_msg(format!("Remailer `{}` is not in the config!", email))
+                    })?;
+                Ok(Hop {
+                    email: email.clone(),
+                    fingerprint: remailer.fingerprint().ok(),
+                    headers: headers.clone(),
+                    delay: delay.clone(),
+                })
+            })
+            .collect::<Fallible<Vec<Hop>>>()?;
+        Ok(Route { hops })
+    }
+
+    /// Check the headers of this route and that each remailer can honour the headers it reads
+    /// (the first remailer reads the headers of the message envelope).
+    /// Return warnings about unknown headers.
+    pub fn check(&self, remailers: &[Remailer]) -> Fallible<Vec<String>> {
+        let mut warnings = Vec::new();
+        for (index, hop) in self.hops.iter().enumerate() {
+            let reader = &self.hops[index.saturating_sub(1)];
+            let remailer = remailers
+                .iter()
+                .find(|remailer| remailer.email == reader.email);
+            // Each remailer must decrypt its layer
+            if let Some(remailer) = remailers
+                .iter()
+                .find(|remailer| remailer.email == hop.email)
+            {
+                if !remailer.has_capability("pgp") {
+                    return Err(err_msg(format!(
+                        "Remailer `{}` can't decrypt PGP messages!",
+                        hop.email
+                    )));
+                }
+            }
+            for header in hop.all_headers() {
+                let header = Header::parse(&header)?;
+                if header.is(LATENT_TIME) && !is_latent_time(&header.value) {
+                    return Err(err_msg(format!(
+                        "Invalid delay `{}`, it must be `[+]HH:MM[r]`!",
+                        header.value
+                    )));
+                }
+                match KNOWN_HEADERS.iter().find(|(known, _)| header.is(known)) {
+                    Some((_, Some(capability))) => {
+                        if let Some(remailer) = remailer {
+                            if !remailer.has_capability(capability) {
+                                return Err(err_msg(format!(
+                                    "Remailer `{}` can't honour the header `{}` (no `{}` capability)!",
+                                    remailer.email, header.name, capability
+                                )));
+                            }
+                        }
+                    }
+                    Some((_, None)) => {}
+                    None => warnings.push(format!(
+                        "Unknown header `{}` for remailer `{}`, it may be ignored",
+                        header.name, reader.email
+                    )),
+                }
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// Return the layers of the onion of this route, from the first remailer to the last one,
+    /// for a message of the given size.
+    /// The size of the message sent to the first remailer is returned too.
+    pub fn layers(&self, message_size: usize) -> (Vec<Layer>, usize) {
+        let mut layers = Vec::new();
+        // From the farther remailer, which decrypts the message itself
+        let mut plain_size = message_size;
+        for (index, hop) in self.hops.iter().enumerate().rev() {
+            let size = encrypted_size(plain_size);
+            let (anon_to, headers) = match self.hops.get(index + 1) {
+                Some(next) => (Some(next.email.clone()), next.all_headers()),
+                None => (None, vec![]),
+            };
+            layers.push(Layer {
+                remailer: hop.email.clone(),
+                anon_to,
+                headers,
+                size,
+            });
+            plain_size = wrapper_size(hop) + size;
+        }
+        layers.reverse();
+        (layers, plain_size)
+    }
+
+    /// Return the remailers' emails of this route
+    pub fn emails(&self) -> Vec<String> {
+        self.hops.iter().map(|hop| hop.email.clone()).collect()
+    }
+}
+
+impl Hop {
+    /// Return all the headers to write next to the `Anon-To` of this hop, delay included
+    pub fn all_headers(&self) -> Vec<String> {
+        let mut headers = self.headers.clone();
+        if let Some(delay) = &self.delay {
+            headers.push(format!("{}: {}", LATENT_TIME, delay));
+        }
+        headers
+    }
+
+    /// Return the recipient of the encryption for this hop: its key, if known, otherwise its email
+    pub fn recipient(&self) -> String {
+        self.fingerprint
+            .clone()
+            .unwrap_or_else(|| self.email.clone())
+    }
+}
+
+/// Return the size of the cleartext wrapper of a hop, with its headers and `Anon-To`
+fn wrapper_size(hop: &Hop) -> usize {
+    let headers: usize = hop
+        .all_headers()
+        .iter()
+        .map(|header| header.len() + 1)
+        .sum();
+    "::\n".len()
+        + headers
+        + "Anon-To: ".len()
+        + hop.email.len()
+        + "\n\n::\nEncrypted: PGP\n\n".len()
+}
+
+/// Estimate the size of an ASCII-armored PGP message encrypting the given size
+fn encrypted_size(size: usize) -> usize {
+    let armored = (size + PGP_OVERHEAD).div_ceil(3) * 4;
+    // Lines of 64 chars, armor header, checksum and footer
+    armored + armored / 64 + 64
+}
+
+/// Return if the value of a `Latent-Time` header is valid: `[+]HH:MM[r]`
+pub fn is_latent_time(value: &str) -> bool {
+    let value = value.trim_start_matches('+').trim_end_matches('r');
+    match value.split_once(':') {
+        Some((hours, minutes)) => {
+            !hours.is_empty()
+                && hours.chars().all(|c| c.is_ascii_digit())
+                && minutes.len() == 2
+                && minutes.chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
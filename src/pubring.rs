@@ -0,0 +1,209 @@
+use std::collections::HashSet;
+
+use failure::{Fallible, ResultExt};
+
+use crate::config::Remailer;
+use crate::pgp::{armor, packet};
+
+/// A remailer described by a `remailer-conf`-style line of a pinger:
+/// `$remailer{"austria"} = "<mixmaster@remailer.privacy.at> cpunk pgp latent hash";`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RemailerConf {
+    pub name: String,
+    pub email: String,
+    pub capabilities: Vec<String>,
+}
+
+/// A type-II (Mixmaster) remailer of a `pubring.mix` or a `type2.list`: only its name and its
+/// email are kept, as reference metadata
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Type2Remailer {
+    pub name: String,
+    pub email: String,
+}
+
+/// Everything known about the remailers from the files of a pinger: the keys of a
+/// `pubring.asc`, the `remailer-conf` lines and the type-II lists
+#[derive(Clone, Debug, Default)]
+pub struct Pubring {
+    pub keys: Vec<Vec<u8>>,
+    pub confs: Vec<RemailerConf>,
+    pub type2: Vec<Type2Remailer>,
+}
+
+impl Pubring {
+    /// Read a pinger's file, whatever it is, and add what it tells about the remailers
+    pub fn read(&mut self, text: &str) -> Fallible<()> {
+        if text.contains("-----BEGIN PGP PUBLIC KEY BLOCK-----") {
+            let keyring = armor::decode(text)?;
+            let keys = packet::split_keys(&keyring).context("Invalid keyring")?;
+            self.keys.extend(keys.into_iter().map(<[u8]>::to_vec));
+        }
+        self.confs.extend(text.lines().filter_map(parse_conf));
+        self.type2.extend(text.lines().filter_map(parse_type2));
+        Ok(())
+    }
+
+    /// Make a remailer of each key: its email is the one of its user IDs known by the other
+    /// files (or the first one), its aliases are its names in the `remailer-conf` lines, in the
+    /// type-II lists and in its user ID, its capabilities are the ones of its `remailer-conf`
+    /// line. An alias already given to another remailer is skipped.
+    pub fn remailers(&self) -> Fallible<Vec<Remailer>> {
+        let mut aliases = HashSet::new();
+        let mut remailers = Vec::new();
+        for key in &self.keys {
+            let user_ids = packet::user_ids(key)?;
+            let user_id = user_ids
+                .iter()
+                .find(|user_id| self.is_known(packet::user_id_email(user_id)))
+                .or_else(|| user_ids.first());
+            let user_id = match user_id {
+                Some(user_id) => user_id,
+                None => continue,
+            };
+            let email = packet::user_id_email(user_id).to_string();
+            let conf = self
+                .confs
+                .iter()
+                .find(|conf| conf.email.eq_ignore_ascii_case(&email));
+            let type2 = self
+                .type2
+                .iter()
+                .find(|remailer| remailer.email.eq_ignore_ascii_case(&email));
+
+            let mut name: Vec<String> = conf
+                .map(|conf| conf.name.clone())
+                .into_iter()
+                .chain(type2.map(|remailer| remailer.name.clone()))
+                .chain(user_id_alias(user_id))
+                .filter(|alias| aliases.insert(alias.clone()))
+                .collect();
+            if name.is_empty() {
+                name.push(email.clone());
+            }
+            let capabilities = conf
+                .map(|conf| conf.capabilities.clone())
+                .unwrap_or_default();
+            remailers.push(Remailer {
+                name,
+                enable: capabilities.is_empty() || capabilities.iter().any(|cap| cap == "cpunk"),
+                key: format!("base64:{}", base64::encode(key)),
+                capabilities,
+                pinned_fingerprint: Some(packet::fingerprint(key)?),
+                email,
+                ..Remailer::default()
+            });
+        }
+        Ok(remailers)
+    }
+
+    /// Return if an email is given by the `remailer-conf` lines or the type-II lists
+    fn is_known(&self, email: &str) -> bool {
+        self.confs
+            .iter()
+            .any(|conf| conf.email.eq_ignore_ascii_case(email))
+            || self
+                .type2
+                .iter()
+                .any(|remailer| remailer.email.eq_ignore_ascii_case(email))
+    }
+}
+
+/// Update a remailer list with imported remailers: the known ones, matched by email, get the
+/// new key, capabilities and aliases, but keep their `enable`; the others are added.
+pub fn update_remailers(local: &[Remailer], imported: &[Remailer]) -> Vec<Remailer> {
+    let mut remailers = local.to_vec();
+    for remailer in imported {
+        match remailers
+            .iter_mut()
+            .find(|known| known.email.eq_ignore_ascii_case(&remailer.email))
+        {
+            Some(known) => {
+                known.key = remailer.key.clone();
+                known.pinned_fingerprint = remailer.pinned_fingerprint.clone();
+                known.capabilities = remailer.capabilities.clone();
+                known.base_dir = None;
+                for alias in &remailer.name {
+                    if !known.name.contains(alias) {
+                        known.name.push(alias.clone());
+                    }
+                }
+            }
+            None => remailers.push(remailer.clone()),
+        }
+    }
+    remailers
+}
+
+/// Update the keys of the known remailers with imported keys: a key replaces, and pins, the key
+/// of the remailer having its email as one of its user IDs. Return the updated remailers, and
+/// the user IDs of the keys of no known remailer.
+pub fn update_keys(local: &[Remailer], keys: &[Vec<u8>]) -> Fallible<(Vec<Remailer>, Vec<String>)> {
+    let mut remailers = local.to_vec();
+    let mut unknown = Vec::new();
+    for key in keys {
+        let user_ids = packet::user_ids(key)?;
+        let known = remailers.iter_mut().find(|remailer| {
+            user_ids
+                .iter()
+                .any(|user_id| packet::user_id_email(user_id).eq_ignore_ascii_case(&remailer.email))
+        });
+        match known {
+            Some(known) => {
+                known.key = format!("base64:{}", base64::encode(key));
+                known.pinned_fingerprint = Some(packet::fingerprint(key)?);
+                known.base_dir = None;
+            }
+            None => unknown.push(user_ids.join(", ")),
+        }
+    }
+    Ok((remailers, unknown))
+}
+
+/// Parse a `$remailer{"name"} = "<email> capabilities";` line
+pub fn parse_conf(line: &str) -> Option<RemailerConf> {
+    let line = line.trim().strip_prefix("$remailer{\"")?;
+    let (name, line) = line.split_at(line.find('"')?);
+    let line = line.strip_prefix("\"}")?.trim_start().strip_prefix('=')?;
+    let line = line
+        .trim()
+        .trim_end_matches(';')
+        .trim_end()
+        .strip_prefix('"')?;
+    let line = line.strip_suffix('"')?.trim_start().strip_prefix('<')?;
+    let (email, capabilities) = line.split_at(line.find('>')?);
+    Some(RemailerConf {
+        name: name.to_string(),
+        email: email.to_string(),
+        capabilities: capabilities[1..]
+            .split_whitespace()
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+/// Parse a type-II remailer line: `name email keyid version capabilities [from to]`, the key ID
+/// being 32 hexadecimal digits
+pub fn parse_type2(line: &str) -> Option<Type2Remailer> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.as_slice() {
+        [name, email, keyid, _version, ..]
+            if email.contains('@')
+                && keyid.len() == 32
+                && keyid.chars().all(|digit| digit.is_ascii_hexdigit()) =>
+        {
+            Some(Type2Remailer {
+                name: name.to_string(),
+                email: email.to_string(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Return an alias from a user ID: the first word of its name, in lower case
+fn user_id_alias(user_id: &str) -> Option<String> {
+    let name = user_id.split('<').next()?.trim().trim_matches('"');
+    let word = name.split_whitespace().next()?;
+    Some(word.to_lowercase())
+}
@@ -0,0 +1,279 @@
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use failure::{Fallible, ResultExt};
+
+use crate::config::{Remailer, RemailerStats};
+use crate::message::Header;
+use crate::pgp::{armor, packet};
+use crate::pubring::parse_conf;
+use crate::remailer::Delivery;
+
+/// The requests answered by the Type-I remailers: with their key, and with their capabilities
+pub const REQUESTS: &[&str] = &["remailer-key", "remailer-conf"];
+
+/// An email of a mailbox: its sender, its subject and its body
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Email {
+    pub from: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Email {
+    /// Parse an email, its headers being unfolded; return none without `From` header
+    pub fn parse(text: &str) -> Option<Email> {
+        let text = text.replace("\r\n", "\n");
+        let (head, body) = text.split_once("\n\n").unwrap_or((&text, ""));
+        let mut headers: Vec<String> = Vec::new();
+        for line in head.lines() {
+            match headers.last_mut() {
+                Some(last) if line.starts_with(' ') || line.starts_with('\t') => {
+                    last.push(' ');
+                    last.push_str(line.trim());
+                }
+                _ => headers.push(line.to_string()),
+            }
+        }
+        let header = |name: &str| {
+            headers
+                .iter()
+                .filter_map(|line| Header::parse(line).ok())
+                .find(|header| header.is(name))
+                .map(|header| header.value)
+        };
+        Some(Email {
+            from: packet::user_id_email(&header("From")?).to_string(),
+            subject: header("Subject").unwrap_or_default(),
+            body: body.to_string(),
+        })
+    }
+}
+
+/// Read the emails of a mailbox: a Maildir (its `cur` and `new` emails, left in place) or an
+/// mbox file
+pub fn read_mailbox(path: &Path) -> Fallible<Vec<Email>> {
+    let texts = if path.is_dir() {
+        let mut files = Vec::new();
+        for dir in &["cur", "new"] {
+            let dir = path.join(dir);
+            if dir.is_dir() {
+                for entry in fs::read_dir(&dir).context(format!("Cannot read {:?}", dir))? {
+                    files.push(entry?.path());
+                }
+            }
+        }
+        files.sort();
+        files
+            .iter()
+            .map(|file| {
+                Ok(String::from_utf8_lossy(
+                    &fs::read(file).context(format!("Cannot read the email {:?}", file))?,
+                )
+                .into_owned())
+            })
+            .collect::<Fallible<Vec<String>>>()?
+    } else {
+        let mbox = fs::read(path).context(format!("Cannot read the mailbox {:?}", path))?;
+        split_mbox(&String::from_utf8_lossy(&mbox))
+    };
+    Ok(texts.iter().filter_map(|text| Email::parse(text)).collect())
+}
+
+/// Split an mbox into its emails, on the `From ` lines; the `>From ` lines are unquoted
+pub fn split_mbox(mbox: &str) -> Vec<String> {
+    let mut emails: Vec<String> = Vec::new();
+    let mut previous_empty = true;
+    for line in mbox.lines() {
+        if previous_empty && line.starts_with("From ") {
+            emails.push(String::new());
+        } else if let Some(email) = emails.last_mut() {
+            let unquoted = line.trim_start_matches('>');
+            if unquoted.starts_with("From ") && unquoted.len() < line.len() {
+                email.push_str(&line[1..]);
+            } else {
+                email.push_str(line);
+            }
+            email.push('\n');
+        }
+        previous_empty = line.is_empty();
+    }
+    emails
+}
+
+/// Refresh the remailers with the replies to the `remailer-key` and `remailer-conf` requests
+/// found among the emails: each reply from a known remailer gives its key (the one with its
+/// email as user ID), its capabilities (its `$remailer{...}` line) or its statistics (its line
+/// of a pinger's list). Return the refreshed remailers, and a note about each reply ignored.
+pub fn refresh(remailers: &[Remailer], emails: &[Email]) -> (Vec<Remailer>, Vec<String>) {
+    let mut remailers = remailers.to_vec();
+    let mut notes = Vec::new();
+    for email in emails {
+        let remailer = match remailers
+            .iter_mut()
+            .find(|remailer| remailer.email.eq_ignore_ascii_case(&email.from))
+        {
+            Some(remailer) => remailer,
+            None => {
+                notes.push(format!(
+                    "`{}` from {} ignored, not a known remailer",
+                    email.subject, email.from
+                ));
+                continue;
+            }
+        };
+        let mut found = false;
+        if email.body.contains("-----BEGIN PGP PUBLIC KEY BLOCK-----") {
+            match reply_key(&email.body, &remailer.email) {
+                Ok(Some(key)) => {
+                    remailer.pinned_fingerprint = packet::fingerprint(&key).ok();
+                    remailer.key = format!("base64:{}", base64::encode(&key));
+                    remailer.base_dir = None;
+                    found = true;
+                }
+                Ok(None) => notes.push(format!(
+                    "The key sent by {} is not for its email, ignored",
+                    email.from
+                )),
+                Err(err) => notes.push(format!(
+                    "The key sent by {} is invalid, ignored: {}",
+                    email.from, err
+                )),
+            }
+        }
+        for line in email.body.lines() {
+            if let Some(conf) = parse_conf(line) {
+                if conf.email.eq_ignore_ascii_case(&remailer.email) {
+                    remailer.capabilities = conf.capabilities;
+                    found = true;
+                }
+            } else if let Some((address, stats)) = parse_stats(line) {
+                if address.eq_ignore_ascii_case(&remailer.email) {
+                    remailer.stats = Some(stats);
+                    found = true;
+                }
+            }
+        }
+        if !found {
+            notes.push(format!(
+                "`{}` from {} ignored, neither a key nor capabilities",
+                email.subject, email.from
+            ));
+        }
+    }
+    (remailers, notes)
+}
+
+/// Return the key of a reply whose user ID is the remailer's email, if any
+fn reply_key(body: &str, email: &str) -> Fallible<Option<Vec<u8>>> {
+    let keyring = armor::decode(body)?;
+    for key in packet::split_keys(&keyring)? {
+        if packet::user_ids(key)?
+            .iter()
+            .any(|user_id| packet::user_id_email(user_id).eq_ignore_ascii_case(email))
+        {
+            return Ok(Some(key.to_vec()));
+        }
+    }
+    Ok(None)
+}
+
+/// Parse the line of a remailer in a pinger's list: `name email history latency uptime`, as
+/// `dizum remailer@dizum.com ++++++++++++ 18:31 99.95%`
+pub fn parse_stats(line: &str) -> Option<(String, RemailerStats)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.as_slice() {
+        [_, email, .., latency, uptime]
+            if email.contains('@') && latency.contains(':') && uptime.ends_with('%') =>
+        {
+            Some((
+                email.to_string(),
+                RemailerStats {
+                    latency: latency.to_string(),
+                    uptime: uptime.to_string(),
+                },
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Make the `remailer-key` and `remailer-conf` requests to each remailer, from the given address
+/// if any
+pub fn requests(remailers: &[Remailer], from: Option<&str>) -> Vec<Delivery> {
+    let mut deliveries = Vec::new();
+    for remailer in remailers {
+        for request in REQUESTS {
+            let mut headers: Vec<Header> = from
+                .map(|from| Header::new("From", from))
+                .into_iter()
+                .collect();
+            headers.push(Header::new("Subject", request));
+            deliveries.push(Delivery {
+                to: remailer.email.clone(),
+                headers,
+                body: String::new(),
+            });
+        }
+    }
+    deliveries
+}
+
+/// Write emails as an mbox, the `From ` lines of their bodies being quoted
+pub fn to_mbox(deliveries: &[Delivery]) -> String {
+    let date = mbox_date(SystemTime::now());
+    let mut mbox = String::new();
+    for delivery in deliveries {
+        mbox.push_str(&format!("From cypherpunk-cli {}\n", date));
+        for line in delivery.to_string().lines() {
+            if line.trim_start_matches('>').starts_with("From ") {
+                mbox.push('>');
+            }
+            mbox.push_str(line);
+            mbox.push('\n');
+        }
+        mbox.push('\n');
+    }
+    mbox
+}
+
+/// Format a time as the date of the `From ` lines of an mbox: `Thu Jan  1 00:00:00 1970` (UTC)
+pub fn mbox_date(time: SystemTime) -> String {
+    const DAYS: &[&str] = &["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: &[&str] = &[
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let days = seconds / 86400;
+    let (year, month, day) = civil_date(days);
+    format!(
+        "{} {} {:2} {:02}:{:02}:{:02} {}",
+        DAYS[(days % 7) as usize],
+        MONTHS[(month - 1) as usize],
+        day,
+        seconds % 86400 / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+        year
+    )
+}
+
+/// Return the civil date (year, month, day) of a number of days since the epoch (Howard
+/// Hinnant's algorithm)
+pub fn civil_date(days: u64) -> (u64, u64, u64) {
+    let shifted = days + 719_468;
+    let era = shifted / 146_097;
+    let day_of_era = shifted % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Cursor;
+
+use failure::{err_msg, Fallible, ResultExt};
+use percent_encoding::percent_decode_str;
+
+use crate::message::{Header, Message};
+use crate::PGPBackend;
+
+/// The headers giving the next address of a message
+const ANON_TO: &[&str] = &["Anon-To", "Request-Remailing-To", "Remail-To"];
+
+/// The `##` headers never added to the email: the addresses, which the delivery command (as
+/// `sendmail -t`) would follow or show as the sender; the `Resent-*` ones too
+const ADDRESS_HEADERS: &[&str] = &["To", "Cc", "Bcc", "From", "Sender"];
+
+/// The maximum number of remailers a peeled message can go through
+const MAX_HOPS: usize = 100;
+
+/// What a Type-I remailer does with a message
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Action {
+    /// Send the body to an address, honouring the other `::` headers (as `Latent-Time`) and
+    /// adding the `##` headers to the email, except the address ones (see `is_mail_header`)
+    Forward {
+        to: String,
+        headers: Vec<Header>,
+        mail_headers: Vec<Header>,
+        body: String,
+    },
+    /// Drop the message (`Null:` header)
+    Drop,
+}
+
+/// A remailer which forwarded a peeled message
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Step {
+    pub remailer: String,
+    pub to: String,
+    pub headers: Vec<Header>,
+}
+
+/// An email delivered by the last remailer
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Delivery {
+    pub to: String,
+    pub headers: Vec<Header>,
+    pub body: String,
+}
+
+impl fmt::Display for Delivery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "To: {}", self.to)?;
+        for header in &self.headers {
+            writeln!(f, "{}", header)?;
+        }
+        write!(f, "\n{}", self.body)
+    }
+}
+
+/// A peeled message: its path through the remailers and the email finally delivered, if it
+/// wasn't dropped
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Peeled {
+    pub steps: Vec<Step>,
+    pub delivery: Option<Delivery>,
+}
+
+/// Process a message the way a Type-I remailer would: decrypt its `Encrypted: PGP` blocks with
+/// the keys of the PGP backend, then follow its `::` headers
+pub fn remail(pgp: &dyn PGPBackend, text: &str) -> Fallible<Action> {
+    let mut message = Message::parse(text)?;
+    loop {
+        if message.headers.is_empty() {
+            return Err(err_msg("Not a remailer message (no `::` headers)"));
+        }
+        if message.header("Null").is_some() {
+            return Ok(Action::Drop);
+        }
+        match message.header("Encrypted") {
+            Some(kind) if kind.eq_ignore_ascii_case("PGP") => {
+                let decrypted = decrypt(pgp, &message.body)?;
+                message = Message::parse(&decrypted).context("Malformed decrypted layer")?;
+            }
+            Some(kind) => return Err(err_msg(format!("Unsupported encryption `{}`", kind))),
+            None => break,
+        }
+    }
+    let to = ANON_TO
+        .iter()
+        .find_map(|name| message.header(name))
+        .ok_or_else(|| err_msg("No `Anon-To` header in the message"))?
+        .to_string();
+    if to.contains(['\r', '\n']) {
+        return Err(err_msg("Invalid `Anon-To` header (line break)"));
+    }
+    Ok(Action::Forward {
+        to,
+        headers: message
+            .headers
+            .into_iter()
+            .filter(|header| !ANON_TO.iter().any(|name| header.is(name)))
+            .collect(),
+        mail_headers: message
+            .hash_headers
+            .into_iter()
+            .filter(is_mail_header)
+            .collect(),
+        body: message.body,
+    })
+}
+
+/// Return if a `##` header can be added to the email: neither an address header (see
+/// `ADDRESS_HEADERS`) nor a header with a line break, which would add other headers
+fn is_mail_header(header: &Header) -> bool {
+    !ADDRESS_HEADERS.iter().any(|name| header.is(name))
+        && !header.name.to_ascii_lowercase().starts_with("resent-")
+        && !header.to_string().contains(['\r', '\n'])
+}
+
+/// Decrypt the ASCII-armored PGP message of a text
+fn decrypt(pgp: &dyn PGPBackend, text: &str) -> Fallible<String> {
+    const BEGIN: &str = "-----BEGIN PGP MESSAGE-----";
+    const END: &str = "-----END PGP MESSAGE-----";
+    let start = text
+        .find(BEGIN)
+        .ok_or_else(|| err_msg("No PGP message in the encrypted block"))?;
+    let end = text[start..]
+        .find(END)
+        .ok_or_else(|| err_msg("Truncated PGP message in the encrypted block"))?
+        + start
+        + END.len();
+
+    let mut output = Vec::new();
+    pgp.decrypt(&mut Cursor::new(&text[start..end]), &mut output)
+        .context("Cannot decrypt the PGP message")?;
+    Ok(String::from_utf8(output).context("The decrypted layer is not a valid utf-8 text")?)
+}
+
+/// Return the address and the body of a message made by the client, in Cypherpunk, EML or
+/// mailto format
+pub fn read_envelope(text: &str) -> Fallible<(String, String)> {
+    let text = text.replace("\r\n", "\n");
+    let text = text.trim_start();
+    // Mailto URL
+    if let Some(url) = text.strip_prefix("mailto:") {
+        let (to, body) = url
+            .trim_end()
+            .split_once("?body=")
+            .ok_or_else(|| err_msg("Invalid mailto URL (body missing)"))?;
+        let body = percent_decode_str(body)
+            .decode_utf8()
+            .context("Invalid mailto URL (body is not utf-8)")?;
+        return Ok((to.to_string(), body.into_owned()));
+    }
+    // Cypherpunk message
+    if text.starts_with("::") {
+        let message = Message::parse(text)?;
+        let to = message
+            .header("Anon-To")
+            .ok_or_else(|| err_msg("Invalid Cypherpunk message (Anon-To header missing)"))?;
+        return Ok((to.to_string(), message.body));
+    }
+    // EML email
+    let (headers, body) = text
+        .split_once("\n\n")
+        .ok_or_else(|| err_msg("Invalid email (body not found)"))?;
+    let to = headers
+        .lines()
+        .filter_map(|line| Header::parse(line).ok())
+        .find(|header| header.is("To"))
+        .ok_or_else(|| err_msg("Invalid email (To header missing)"))?;
+    Ok((to.value, body.to_string()))
+}
+
+/// Peel a message made by the client, each layer being decrypted by the remailer it is sent
+/// to, with the PGP backend of this remailer (holding its secret key), until the message
+/// leaves the known remailers.
+pub fn peel<P: PGPBackend>(remailers: &HashMap<String, P>, text: &str) -> Fallible<Peeled> {
+    let (mut to, mut body) = read_envelope(text)?;
+    let mut steps = Vec::new();
+    let mut mail_headers = Vec::new();
+    while let Some(pgp) = remailers.get(&to.to_lowercase()) {
+        if steps.len() >= MAX_HOPS {
+            return Err(err_msg("The message loops between the remailers!"));
+        }
+        match remail(pgp, &body).context(format!("Remailer `{}` can't process its layer!", to))? {
+            Action::Forward {
+                to: next,
+                headers,
+                mail_headers: next_mail_headers,
+                body: next_body,
+            } => {
+                steps.push(Step {
+                    remailer: to,
+                    to: next.clone(),
+                    headers,
+                });
+                to = next;
+                body = next_body;
+                mail_headers = next_mail_headers;
+            }
+            Action::Drop => {
+                return Ok(Peeled {
+                    steps,
+                    delivery: None,
+                })
+            }
+        }
+    }
+    if steps.is_empty() {
+        return Err(err_msg(format!(
+            "No secret key for `{}`, the first remailer!",
+            to
+        )));
+    }
+    Ok(Peeled {
+        steps,
+        delivery: Some(Delivery {
+            to,
+            headers: mail_headers,
+            body,
+        }),
+    })
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use failure::{err_msg, Fallible, ResultExt};
+use tempfile::TempDir;
+
+use crate::config::{Remailer, RemailerConfig};
+use crate::format::{format_msg, OutputFormat};
+use crate::migrate::CURRENT_SCHEMA;
+use crate::plan::Route;
+use crate::remailer::{peel, Peeled};
+use crate::{Cypherpunk, CypherpunkCore, PGPBackend};
+
+/// The stages run by the self-test, in order
+pub const STAGES: &[&str] = &["import", "encrypt", "decrypt", "format"];
+
+/// The number of remailers of the self-test network
+const HOPS: usize = 3;
+
+/// The recipient and the body of the self-test message
+const TO: &str = "selftest@test.invalid";
+const BODY: &str = "This is the cypherpunk-cli self-test.\n";
+
+/// The outcome of a self-test stage: a short report, or why it failed
+#[derive(Debug)]
+pub struct Stage {
+    pub name: &'static str,
+    pub outcome: Fallible<String>,
+}
+
+impl Stage {
+    /// Return true if the stage passed
+    pub fn passed(&self) -> bool {
+        self.outcome.is_ok()
+    }
+}
+
+/// Exercise a PGP backend end to end: generate ephemeral keys for a 3-hop network, import
+/// them, encrypt a known message, decrypt each layer and check the output formats.
+///
+/// Each backend is made by `backend` from its own temporary home directory. The stages are
+/// run in order (see `STAGES`) and the self-test stops at the first failed stage.
+pub fn selftest<P, F>(mut backend: F) -> Vec<Stage>
+where
+    P: PGPBackend,
+    F: FnMut(&Path) -> P,
+{
+    let mut stages = Vec::new();
+    run(&mut backend, &mut stages);
+    stages
+}
+
+/// Run the stages, stopping at the first failed one
+fn run<P, F>(backend: &mut F, stages: &mut Vec<Stage>) -> Option<()>
+where
+    P: PGPBackend,
+    F: FnMut(&Path) -> P,
+{
+    // Import: the keys of the network, in the client's keyring
+    let (network, core, _homedir) = stage(stages, "import", || {
+        let network = Network::generate(HOPS, &mut *backend)
+            .context("Cannot generate the keys of the test remailers")?;
+        let homedir = tempfile::tempdir().context("Cannot create a temporary home directory!")?;
+        let core = CypherpunkCore::new(backend(homedir.path()));
+        let keys = network
+            .config()
+            .remailers
+            .iter()
+            .map(|remailer| remailer.as_key())
+            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+        core.import_keys(keys)?;
+        let report = format!("{} ephemeral keys generated and imported", HOPS);
+        Ok(((network, core, homedir), report))
+    })?;
+
+    // Encrypt: the message through all the remailers of the network
+    let output = stage(stages, "encrypt", || {
+        let chain: Vec<String> = network
+            .config()
+            .remailers
+            .iter()
+            .map(|remailer| remailer.email.clone())
+            .collect();
+        let route = Route::new(&chain, &[], &network.config().remailers)?;
+        let message = format!("::\nAnon-To: {}\n\n{}", TO, BODY);
+        let output = core.encrypt_route(&route.hops, message.into_bytes())?;
+        let output = String::from_utf8(output)
+            .map_err(|_| err_msg("The encrypted message is not a valid utf-8 string"))?;
+        let report = format!("{} bytes for {}", output.len(), chain.join(", "));
+        Ok((output, report))
+    })?;
+
+    // Decrypt: each layer, as the remailers would
+    stage(stages, "decrypt", || {
+        let hops = deliver(&network, &output)?;
+        Ok((
+            (),
+            format!("{} layers decrypted, delivered to {}", hops, TO),
+        ))
+    })?;
+
+    // Format: the other output formats reach the first remailer too
+    stage(stages, "format", || {
+        let mut formats = Vec::new();
+        for format in &[OutputFormat::Mailto, OutputFormat::EML] {
+            let formatted = format_msg(*format, output.clone())?;
+            deliver(&network, &formatted).context(format!("Format {} failed", format))?;
+            formats.push(format.to_string().to_lowercase());
+        }
+        Ok(((), format!("{} messages delivered", formats.join(" and "))))
+    })
+}
+
+/// Run a stage and record its outcome, return its result if it passed
+fn stage<T, F>(stages: &mut Vec<Stage>, name: &'static str, run: F) -> Option<T>
+where
+    F: FnOnce() -> Fallible<(T, String)>,
+{
+    match run() {
+        Ok((result, report)) => {
+            stages.push(Stage {
+                name,
+                outcome: Ok(report),
+            });
+            Some(result)
+        }
+        Err(err) => {
+            stages.push(Stage {
+                name,
+                outcome: Err(err),
+            });
+            None
+        }
+    }
+}
+
+/// Peel a message through the network, check it is delivered intact and return its number
+/// of hops
+fn deliver<P: PGPBackend>(network: &Network<P>, message: &str) -> Fallible<usize> {
+    let peeled = network.deliver(message)?;
+    let delivery = peeled
+        .delivery
+        .ok_or_else(|| err_msg("The message was dropped by a remailer"))?;
+    if delivery.to != TO {
+        return Err(err_msg(format!(
+            "The message was delivered to a wrong address: {}",
+            delivery.to
+        )));
+    }
+    if delivery.body != BODY {
+        return Err(err_msg("The delivered message is altered"));
+    }
+    Ok(peeled.steps.len())
+}
+
+/// A simulated network of Type-I remailers: each remailer has its own throwaway key pair, and
+/// decrypts and forwards the messages to the others in memory (see `testnet::TestNetwork` for
+/// the tests)
+pub(crate) struct Network<P: PGPBackend> {
+    nodes: HashMap<String, P>,
+    config: RemailerConfig,
+    // The home directories of the remailers' keys, removed with the network
+    _homedirs: Vec<TempDir>,
+}
+
+impl<P: PGPBackend> Network<P> {
+    /// Generate a network of `size` remailers, `node1` (`remailer1@test.invalid`) to `nodeN`.
+    /// The PGP backend of each remailer is made by `backend` from its own home directory.
+    pub(crate) fn generate<F>(size: usize, mut backend: F) -> Fallible<Self>
+    where
+        F: FnMut(&Path) -> P,
+    {
+        let mut nodes = HashMap::new();
+        let mut remailers = Vec::new();
+        let mut homedirs = Vec::new();
+        for index in 1..=size {
+            let homedir = tempfile::tempdir()
+                .context("Cannot create a home directory for a test remailer!")?;
+            let pgp = backend(homedir.path());
+            let email = format!("remailer{}@test.invalid", index);
+            let key = pgp
+                .generate_key(&format!("Test remailer {} <{}>", index, email))
+                .context(format!("Cannot generate the key of `{}`", email))?;
+
+            remailers.push(Remailer {
+                name: vec![format!("node{}", index)],
+                email: email.clone(),
+                enable: true,
+                key: format!("base64:{}", base64::encode(&key)),
+                ..Remailer::default()
+            });
+            nodes.insert(email, pgp);
+            homedirs.push(homedir);
+        }
+        Ok(Self {
+            nodes,
+            config: RemailerConfig {
+                schema: CURRENT_SCHEMA,
+                version: "test".to_string(),
+                authors: vec!["cypherpunk-cli test network".to_string()],
+                remailers,
+            },
+            _homedirs: homedirs,
+        })
+    }
+
+    /// Return the remailer config matching this network
+    pub(crate) fn config(&self) -> &RemailerConfig {
+        &self.config
+    }
+
+    /// Send a message made by the client (in any output format) through the network, return
+    /// its path and the delivered email
+    pub(crate) fn deliver(&self, message: &str) -> Fallible<Peeled> {
+        peel(&self.nodes, message)
+    }
+}
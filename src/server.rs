@@ -0,0 +1,211 @@
+use std::fs::{create_dir_all, read_dir, rename};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use failure::{err_msg, Fallible, ResultExt};
+use rand::Rng;
+
+use crate::message::Header;
+use crate::plan::{is_latent_time, LATENT_TIME};
+use crate::remailer::{remail, Action, Delivery};
+use crate::PGPBackend;
+
+/// How often a Maildir is read again for new emails, while the queued emails wait for their delay
+pub const MAILDIR_POLL: Duration = Duration::from_secs(60);
+
+/// A Type-I remailer server: it processes the incoming emails with its secret key, then hands
+/// the resulting emails, when their delay is over, to a local delivery command
+pub struct Server<P: PGPBackend> {
+    pgp: P,
+    deliver: String,
+    queue: Vec<Queued>,
+}
+
+/// An email waiting for its delay, with the Maildir email it comes from if any
+struct Queued {
+    time: SystemTime,
+    delivery: Delivery,
+    source: Option<PathBuf>,
+}
+
+impl<P: PGPBackend> Server<P> {
+    /// Make a server with the PGP backend holding its secret key, and the delivery command
+    /// which reads the emails to send on its standard input (as `sendmail -t`)
+    pub fn new(pgp: P, deliver: &str) -> Self {
+        Self {
+            pgp,
+            deliver: deliver.to_string(),
+            queue: Vec::new(),
+        }
+    }
+
+    /// Process an incoming email and queue the resulting email, if not dropped.
+    /// Return the queued email and its delay. The Maildir email it comes from, if any, is only
+    /// marked as processed once the resulting email is delivered (see `deliver_due`): the
+    /// caller marks the dropped and the refused ones.
+    pub fn receive(
+        &mut self,
+        email: &str,
+        source: Option<&Path>,
+    ) -> Fallible<Option<(Duration, Delivery)>> {
+        // The headers of the incoming email are not kept
+        let body = match email.replace("\r\n", "\n").split_once("\n\n") {
+            Some((_, body)) => body.to_string(),
+            None => return Err(err_msg("Invalid email (body not found)")),
+        };
+        match remail(&self.pgp, &body)? {
+            Action::Forward {
+                to,
+                headers,
+                mail_headers,
+                body,
+            } => {
+                let delay = latency(&headers)?;
+                let delivery = Delivery {
+                    to,
+                    headers: mail_headers,
+                    body,
+                };
+                self.queue.push(Queued {
+                    time: SystemTime::now() + delay,
+                    delivery: delivery.clone(),
+                    source: source.map(Path::to_path_buf),
+                });
+                Ok(Some((delay, delivery)))
+            }
+            Action::Drop => Ok(None),
+        }
+    }
+
+    /// Deliver the queued emails whose delay is over, in the order of their times, and mark
+    /// the Maildir emails they come from as processed. Return the delivered emails. An email
+    /// failing to be delivered stays queued, and its Maildir email unprocessed.
+    pub fn deliver_due(&mut self) -> Fallible<Vec<Delivery>> {
+        self.queue.sort_by_key(|queued| queued.time);
+        let now = SystemTime::now();
+        let mut delivered = Vec::new();
+        while self.queue.first().is_some_and(|queued| queued.time <= now) {
+            let queued = &self.queue[0];
+            run_delivery(&self.deliver, &queued.delivery).context(format!(
+                "Cannot deliver the email to `{}`",
+                queued.delivery.to
+            ))?;
+            let queued = self.queue.remove(0);
+            if let Some(source) = &queued.source {
+                maildir_done(source)?;
+            }
+            delivered.push(queued.delivery);
+        }
+        Ok(delivered)
+    }
+
+    /// Return the time of the next queued email, none if the queue is empty
+    pub fn next_time(&self) -> Option<SystemTime> {
+        self.queue.iter().map(|queued| queued.time).min()
+    }
+
+    /// Return if the result of a Maildir email is waiting in the queue
+    pub fn is_queued(&self, source: &Path) -> bool {
+        self.queue
+            .iter()
+            .any(|queued| queued.source.as_deref() == Some(source))
+    }
+}
+
+/// Return the delay asked by a `Latent-Time` header: `+HH:MM` from now, `HH:MM` as the next
+/// time of the day (UTC), and a random delay up to this one with a final `r`
+pub fn latency(headers: &[Header]) -> Fallible<Duration> {
+    let value = match headers.iter().find(|header| header.is(LATENT_TIME)) {
+        Some(header) => header.value.as_str(),
+        None => return Ok(Duration::from_secs(0)),
+    };
+    if !is_latent_time(value) {
+        return Err(err_msg(format!("Invalid delay `{}`", value)));
+    }
+    let random = value.ends_with('r');
+    let time = value.trim_end_matches('r');
+    let (hours, minutes) = time
+        .trim_start_matches('+')
+        .split_once(':')
+        .ok_or_else(|| err_msg(format!("Invalid delay `{}`", value)))?;
+    let seconds = hours.parse::<u64>()? * 3600 + minutes.parse::<u64>()? * 60;
+    let seconds = if time.starts_with('+') {
+        seconds
+    } else {
+        // Time of the day: wait until it comes
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() % 86400;
+        (seconds + 86400 - now) % 86400
+    };
+    let seconds = if random && seconds > 0 {
+        rand::thread_rng().gen_range(0, seconds + 1)
+    } else {
+        seconds
+    };
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Hand an email to the delivery command
+pub fn run_delivery(command: &str, delivery: &Delivery) -> Fallible<()> {
+    let mut child = if cfg!(target_os = "windows") {
+        Command::new("cmd")
+            .arg("/C")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .spawn()
+    } else {
+        Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .spawn()
+    }
+    .context(format!("Failed to execute `{}`", command))?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| err_msg("Cannot write to the delivery command"))?
+        .write_all(delivery.to_string().as_bytes())
+        .context("Cannot write the email to the delivery command")?;
+    let exit_state = child.wait().context("Delivery command unexpected exit")?;
+    match exit_state.code() {
+        Some(0) => Ok(()),
+        Some(code) => Err(err_msg(format!(
+            "The delivery command exited with code {}",
+            code
+        ))),
+        None => Err(err_msg(
+            "The delivery command exited without any exit code!",
+        )),
+    }
+}
+
+/// Return the new emails of a Maildir
+pub fn maildir_new<Q: AsRef<Path>>(maildir: Q) -> Fallible<Vec<PathBuf>> {
+    let new = maildir.as_ref().join("new");
+    let mut emails = read_dir(&new)
+        .context(format!("Cannot read the Maildir {:?}", new))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Fallible<Vec<PathBuf>>>()?;
+    emails.sort();
+    Ok(emails)
+}
+
+/// Move a processed email of a Maildir from `new` to `cur`, marked as seen
+pub fn maildir_done<Q: AsRef<Path>>(email: Q) -> Fallible<()> {
+    let email = email.as_ref();
+    let cur = email
+        .parent()
+        .and_then(Path::parent)
+        .ok_or_else(|| err_msg(format!("{:?} is not in a Maildir", email)))?
+        .join("cur");
+    create_dir_all(&cur).context(format!("Cannot create {:?}", cur))?;
+    let name = email
+        .file_name()
+        .ok_or_else(|| err_msg(format!("{:?} is not an email file", email)))?
+        .to_string_lossy();
+    rename(email, cur.join(format!("{}:2,S", name)))
+        .context(format!("Cannot move {:?} to {:?}", email, cur))?;
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use failure::{err_msg, Fallible, ResultExt};
+use serde_derive::Deserialize;
+
+use crate::config::{config_dirs, read_config};
+use crate::format::OutputFormat;
+
+/// The file names of the user settings in each config directory, the first one found is read
+pub const SETTINGS_FILES: &[&str] = &[
+    "settings.toml",
+    "settings.json",
+    "settings.yaml",
+    "settings.yml",
+];
+
+/// The profile used without `--profile`
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The user settings: named profiles of default options
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug, Default)]
+pub struct Settings {
+    #[serde(default, rename = "profile")]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// A profile: the options used when not given on the command line. Its chain can be used in
+/// the others chains as `@name`.
+#[derive(Deserialize, Eq, PartialEq, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub chain: Option<Vec<String>>,
+    pub hops: Option<usize>,
+    pub redundancy: Option<u8>,
+    pub headers: Option<Vec<String>>,
+    pub format: Option<String>,
+    pub allow_missing: Option<bool>,
+}
+
+impl Profile {
+    /// Return the output format of the profile, if any
+    pub fn format(&self) -> Fallible<Option<OutputFormat>> {
+        self.format
+            .as_ref()
+            .map(|format| {
+                OutputFormat::from_str(format)
+                    .map_err(|_| err_msg(format!("Unknown output format `{}`", format)))
+            })
+            .transpose()
+    }
+}
+
+impl Settings {
+    /// Return a profile by its name
+    pub fn profile(&self, name: &str) -> Fallible<&Profile> {
+        self.profiles.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            err_msg(format!(
+                "Unknown profile `{}` (known profiles: {})",
+                name,
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            ))
+        })
+    }
+
+    /// Return the chains of the profiles, by profile name
+    pub fn named_chains(&self) -> HashMap<String, Vec<String>> {
+        self.profiles
+            .iter()
+            .filter_map(|(name, profile)| Some((name.clone(), profile.chain.clone()?)))
+            .collect()
+    }
+}
+
+/// Return the settings files searched, from the lowest precedence to the highest: in the system
+/// config directory, the user one, then the working directory
+pub fn settings_paths() -> Vec<PathBuf> {
+    let mut dirs = config_dirs();
+    dirs.push(PathBuf::from("."));
+    dirs.iter()
+        .filter_map(|dir| {
+            SETTINGS_FILES
+                .iter()
+                .map(|name| dir.join(name))
+                .find(|path| path.is_file())
+        })
+        .collect()
+}
+
+/// Read the settings files (JSON, TOML or YAML), a profile replacing the profiles of the same
+/// name in the files before it
+pub fn load_settings(paths: &[PathBuf]) -> Fallible<Settings> {
+    let mut settings = Settings::default();
+    for path in paths {
+        settings.profiles.append(&mut read_settings(path)?.profiles);
+    }
+    Ok(settings)
+}
+
+/// Read a settings file
+fn read_settings(path: &Path) -> Fallible<Settings> {
+    let value = read_config(path)?;
+    Ok(serde_json::from_value(value).context(format!("Invalid settings {:?}", path))?)
+}
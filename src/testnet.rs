@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::path::Path;
+
+use failure::{Fallible, ResultExt};
+
+use crate::config::RemailerConfig;
+#[cfg(feature = "back-gpg")]
+use crate::pgp::gpg::GPGBackend;
+#[cfg(feature = "test-backend")]
+use crate::pgp::mock::MockBackend;
+use crate::remailer::Peeled;
+use crate::selftest::Network;
+use crate::PGPBackend;
+
+/// A simulated network of Type-I remailers, for end-to-end tests: each remailer has its own
+/// throwaway key pair, and decrypts and forwards the messages to the others in memory.
+pub struct TestNetwork<P: PGPBackend>(Network<P>);
+
+impl<P: PGPBackend> TestNetwork<P> {
+    /// Generate a network of `size` remailers, `node1` (`remailer1@test.invalid`) to `nodeN`.
+    /// The PGP backend of each remailer is made by `backend` from its own home directory.
+    pub fn generate<F>(size: usize, backend: F) -> Fallible<Self>
+    where
+        F: FnMut(&Path) -> P,
+    {
+        Network::generate(size, backend).map(TestNetwork)
+    }
+
+    /// Return the remailer config matching this network
+    pub fn config(&self) -> &RemailerConfig {
+        self.0.config()
+    }
+
+    /// Write the remailer config matching this network in a JSON file
+    pub fn write_config<Q: AsRef<Path>>(&self, path: Q) -> Fallible<()> {
+        Ok(serde_json::to_writer_pretty(
+            File::create(path.as_ref())
+                .context(format!("Cannot create file {:?}", path.as_ref()))?,
+            self.config(),
+        )
+        .context(format!("Cannot write the config in {:?}", path.as_ref()))?)
+    }
+
+    /// Send a message made by the client (in any output format) through the network, return
+    /// its path and the delivered email
+    pub fn deliver(&self, message: &str) -> Fallible<Peeled> {
+        self.0.deliver(message)
+    }
+
+    /// Assert that a message made by the client is delivered to `to` with the given body,
+    /// return its path
+    pub fn assert_delivered(&self, message: &str, to: &str, body: &str) -> Peeled {
+        let peeled = self
+            .deliver(message)
+            .unwrap_or_else(|err| panic!("The message was not delivered: {}", err));
+        let delivery = peeled
+            .delivery
+            .clone()
+            .expect("The message was dropped by a remailer");
+        assert_eq!(
+            delivery.to, to,
+            "The message was delivered to a wrong address"
+        );
+        assert_eq!(delivery.body, body, "The delivered message is altered");
+        peeled
+    }
+}
+
+#[cfg(feature = "back-gpg")]
+impl TestNetwork<GPGBackend> {
+    /// Generate a network of `size` remailers using GnuPG
+    pub fn with_gpg(size: usize) -> Fallible<Self> {
+        Self::generate(size, |homedir| {
+            GPGBackend::with_homedir(homedir.to_path_buf(), None, true)
+        })
+    }
+}
+
+#[cfg(feature = "test-backend")]
+impl TestNetwork<MockBackend> {
+    /// Generate a network of `size` remailers using the fake PGP backend
+    pub fn with_mock(size: usize) -> Fallible<Self> {
+        Self::generate(size, |_| MockBackend::new())
+    }
+}
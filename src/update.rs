@@ -0,0 +1,252 @@
+use std::fmt;
+
+use clap::arg_enum;
+
+use crate::config::Remailer;
+
+/// A difference between the local remailer list and a new one
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Change {
+    /// A remailer only in the new list
+    Added { email: String, name: Vec<String> },
+    /// A remailer only in the local list
+    Removed { email: String },
+    /// The key of a remailer changed; the fingerprints are none for unreadable keys
+    Key {
+        email: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// The email of a remailer changed, the remailer being matched by its key or its aliases
+    Email { old: String, new: String },
+    /// The capabilities of a remailer changed
+    Capabilities {
+        email: String,
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fingerprint = |fingerprint: &Option<String>| {
+            fingerprint
+                .clone()
+                .unwrap_or_else(|| "unreadable key".to_string())
+        };
+        let capabilities = |capabilities: &[String]| {
+            if capabilities.is_empty() {
+                "none".to_string()
+            } else {
+                capabilities.join(" ")
+            }
+        };
+        match self {
+            Change::Added { email, name } => {
+                write!(f, "+ added    {} ({})", email, name.join(", "))
+            }
+            Change::Removed { email } => write!(f, "- removed  {}", email),
+            Change::Key { email, old, new } => write!(
+                f,
+                "~ key      {}: {} -> {}",
+                email,
+                fingerprint(old),
+                fingerprint(new)
+            ),
+            Change::Email { old, new } => write!(f, "~ email    {} -> {}", old, new),
+            Change::Capabilities { email, old, new } => write!(
+                f,
+                "~ caps     {}: {} -> {}",
+                email,
+                capabilities(old),
+                capabilities(new)
+            ),
+        }
+    }
+}
+
+arg_enum! {
+    /// How to apply the changes of a new remailer list
+    #[derive(PartialEq, Debug, Copy, Clone)]
+    pub enum MergePolicy {
+        Ask,
+        All,
+        Safe,
+        Report,
+    }
+}
+
+impl MergePolicy {
+    /// Return if a change is accepted, none if the user has to be asked. `Safe` accepts every
+    /// change but the key and email ones, which send the messages elsewhere, and the added
+    /// remailers, whose keys were never reviewed; `Report` none of them.
+    pub fn accepts(self, change: &Change) -> Option<bool> {
+        match self {
+            MergePolicy::Ask => None,
+            MergePolicy::All => Some(true),
+            MergePolicy::Safe => Some(!matches!(
+                change,
+                Change::Key { .. } | Change::Email { .. } | Change::Added { .. }
+            )),
+            MergePolicy::Report => Some(false),
+        }
+    }
+
+    /// Return if a change made by the replies of the remailers (see `refresh`) is accepted, as
+    /// `accepts`, but a key change is never accepted without asking: anybody can send a reply
+    /// in the name of a remailer.
+    pub fn accepts_reply(self, change: &Change) -> Option<bool> {
+        match change {
+            Change::Key { .. } if self == MergePolicy::All => None,
+            change => self.accepts(change),
+        }
+    }
+}
+
+/// Compare the local remailer list with a new one and return their differences: the remailers
+/// are matched by email, then by key, then by alias.
+pub fn diff(local: &[Remailer], new: &[Remailer]) -> Vec<Change> {
+    let pairs = match_remailers(local, new);
+    let mut changes = Vec::new();
+    for (index, remailer) in new.iter().enumerate() {
+        match pairs.iter().find(|(_, paired)| *paired == index) {
+            Some((old, _)) => changes.append(&mut compare(&local[*old], remailer)),
+            None => changes.push(Change::Added {
+                email: remailer.email.clone(),
+                name: remailer.name.clone(),
+            }),
+        }
+    }
+    for (index, remailer) in local.iter().enumerate() {
+        if !pairs.iter().any(|(paired, _)| *paired == index) {
+            changes.push(Change::Removed {
+                email: remailer.email.clone(),
+            });
+        }
+    }
+    changes
+}
+
+/// Build the updated remailer list: the new one, where the changes not `accepted` are undone.
+/// The local `enable` of each remailer is kept, and its local aliases are added to the new ones.
+pub fn apply(local: &[Remailer], new: &[Remailer], accepted: &[Change]) -> Vec<Remailer> {
+    let pairs = match_remailers(local, new);
+    let mut remailers = Vec::new();
+    for (index, remailer) in new.iter().enumerate() {
+        let old = match pairs.iter().find(|(_, paired)| *paired == index) {
+            Some((old, _)) => &local[*old],
+            None => {
+                let change = Change::Added {
+                    email: remailer.email.clone(),
+                    name: remailer.name.clone(),
+                };
+                if accepted.contains(&change) {
+                    remailers.push(remailer.clone());
+                }
+                continue;
+            }
+        };
+        let mut updated = remailer.clone();
+        updated.enable = old.enable;
+        for alias in &old.name {
+            if !updated.name.contains(alias) {
+                updated.name.push(alias.clone());
+            }
+        }
+        for change in compare(old, remailer) {
+            if accepted.contains(&change) {
+                continue;
+            }
+            match change {
+                Change::Key { .. } => {
+                    updated.key = old.key.clone();
+                    updated.pinned_fingerprint = old.pinned_fingerprint.clone();
+                    updated.base_dir = old.base_dir.clone();
+                }
+                Change::Email { .. } => updated.email = old.email.clone(),
+                Change::Capabilities { .. } => updated.capabilities = old.capabilities.clone(),
+                _ => {}
+            }
+        }
+        remailers.push(updated);
+    }
+    for (index, remailer) in local.iter().enumerate() {
+        let change = Change::Removed {
+            email: remailer.email.clone(),
+        };
+        if !pairs.iter().any(|(paired, _)| *paired == index) && !accepted.contains(&change) {
+            remailers.push(remailer.clone());
+        }
+    }
+    remailers
+}
+
+/// Return the changes between two versions of the same remailer
+fn compare(old: &Remailer, new: &Remailer) -> Vec<Change> {
+    let mut changes = Vec::new();
+    if !old.email.eq_ignore_ascii_case(&new.email) {
+        changes.push(Change::Email {
+            old: old.email.clone(),
+            new: new.email.clone(),
+        });
+    }
+    let (old_fingerprint, new_fingerprint) = (fingerprint(old), fingerprint(new));
+    let changed = match (&old_fingerprint, &new_fingerprint) {
+        (Some(old_fingerprint), Some(new_fingerprint)) => old_fingerprint != new_fingerprint,
+        _ => old.key != new.key,
+    };
+    if changed {
+        changes.push(Change::Key {
+            email: new.email.clone(),
+            old: old_fingerprint,
+            new: new_fingerprint,
+        });
+    }
+    if old.capabilities != new.capabilities {
+        changes.push(Change::Capabilities {
+            email: new.email.clone(),
+            old: old.capabilities.clone(),
+            new: new.capabilities.clone(),
+        });
+    }
+    changes
+}
+
+/// Match the remailers of two lists, first by email, then by key fingerprint, then by alias;
+/// return the pairs of their indexes
+fn match_remailers(local: &[Remailer], new: &[Remailer]) -> Vec<(usize, usize)> {
+    let fingerprints: (Vec<Option<String>>, Vec<Option<String>>) = (
+        local.iter().map(fingerprint).collect(),
+        new.iter().map(fingerprint).collect(),
+    );
+    let rules: [&dyn Fn(usize, usize) -> bool; 3] = [
+        &|old, index| local[old].email.eq_ignore_ascii_case(&new[index].email),
+        &|old, index| fingerprints.0[old].is_some() && fingerprints.0[old] == fingerprints.1[index],
+        &|old, index| {
+            local[old]
+                .name
+                .iter()
+                .any(|alias| new[index].name.contains(alias))
+        },
+    ];
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    for rule in rules.iter() {
+        for old in 0..local.len() {
+            if pairs.iter().any(|(paired, _)| *paired == old) {
+                continue;
+            }
+            let found = (0..new.len()).find(|&index| {
+                !pairs.iter().any(|(_, paired)| *paired == index) && rule(old, index)
+            });
+            if let Some(index) = found {
+                pairs.push((old, index));
+            }
+        }
+    }
+    pairs
+}
+
+/// Return the fingerprint of the key of a remailer, none if unreadable
+fn fingerprint(remailer: &Remailer) -> Option<String> {
+    remailer.fingerprint().ok()
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use cypherpunk_cli::chain::{expand_chain, expand_named_chains, make_chain, select_remailers};
+use cypherpunk_cli::config::{remailer_map, Remailer};
+
+/// Make remailers without keys, `name1` (`remailer1@test1.invalid`) to `nameN`, the even ones
+/// in the `test0.invalid` domain
+fn remailers(size: usize) -> Vec<Remailer> {
+    (1..=size)
+        .map(|index| Remailer {
+            name: vec![format!("name{}", index)],
+            email: format!("remailer{}@test{}.invalid", index, index % 2),
+            enable: true,
+            ..Remailer::default()
+        })
+        .collect()
+}
+
+fn chains(seed: u64, chain: &[String], remailers: &[Remailer]) -> Vec<Vec<String>> {
+    let remmap = remailer_map(remailers.to_vec());
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..5)
+        .map(|_| make_chain(chain, &remmap, remailers, false, &mut rng).unwrap())
+        .collect()
+}
+
+#[test]
+fn seeded_chains_are_reproducible() {
+    let remailers = remailers(5);
+    let chain = expand_chain(&["*,*".to_string(), "name3".to_string()], Some(4)).unwrap();
+    assert_eq!(chain, vec!["*", "*", "*", "name3"]);
+
+    let first = chains(42, &chain, &remailers);
+    assert_eq!(first, chains(42, &chain, &remailers));
+    for chain in first {
+        assert_eq!(chain.len(), 4);
+        assert_eq!(chain[3], "remailer3@test1.invalid");
+        // A random remailer is never the same as its neighbours
+        assert!(chain.windows(2).all(|pair| pair[0] != pair[1]));
+    }
+}
+
+#[test]
+fn unknown_remailers_are_errors() {
+    let mut remailers = remailers(3);
+    remailers[2].enable = false;
+    let remmap = remailer_map(remailers.clone());
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let typo = make_chain(&["nam1".to_string()], &remmap, &remailers, false, &mut rng);
+    assert!(typo
+        .unwrap_err()
+        .to_string()
+        .contains("Did you mean `name1`"));
+    let disabled = make_chain(&["name3".to_string()], &remmap, &remailers, false, &mut rng);
+    assert!(disabled.unwrap_err().to_string().contains("disabled"));
+    let allowed = make_chain(
+        &["name3".to_string(), "name1".to_string()],
+        &remmap,
+        &remailers,
+        true,
+        &mut rng,
+    );
+    // The disabled remailer is replaced by a joker, different from its neighbour
+    assert_eq!(
+        allowed.unwrap(),
+        vec!["remailer2@test0.invalid", "remailer1@test1.invalid"]
+    );
+}
+
+#[test]
+fn named_chains_and_domain_jokers() {
+    let named: HashMap<String, Vec<String>> = vec![
+        ("even".to_string(), vec!["*@test0.invalid".to_string()]),
+        (
+            "safe".to_string(),
+            vec!["@even,*".to_string(), "name1".to_string()],
+        ),
+        (
+            "loop".to_string(),
+            vec!["*".to_string(), "@cycle".to_string()],
+        ),
+        ("cycle".to_string(), vec!["@loop".to_string()]),
+    ]
+    .into_iter()
+    .collect();
+    let chain = expand_named_chains(&["@safe".to_string(), "name3".to_string()], &named).unwrap();
+    assert_eq!(chain, vec!["*@test0.invalid", "*", "name1", "name3"]);
+    assert!(expand_named_chains(&["@loop".to_string()], &named)
+        .unwrap_err()
+        .to_string()
+        .contains("uses itself"));
+    assert!(expand_named_chains(&["@unknown".to_string()], &named).is_err());
+
+    let remailers = remailers(5);
+    for chain in chains(7, &chain, &remailers) {
+        assert!(chain[0].ends_with("@test0.invalid"));
+    }
+    let remmap = remailer_map(remailers.clone());
+    let mut rng = StdRng::seed_from_u64(0);
+    let none = make_chain(&["*@eu".to_string()], &remmap, &remailers, false, &mut rng);
+    assert!(none.unwrap_err().to_string().contains("domain `eu`"));
+}
+
+#[test]
+fn selectors_filter_the_remailers() {
+    let mut remailers = remailers(5);
+    remailers[3].enable = false;
+    let select = |selectors: &[&str]| -> Vec<String> {
+        let selectors: Vec<String> = selectors.iter().map(|s| s.to_string()).collect();
+        select_remailers(&selectors, &remailers)
+            .unwrap()
+            .iter()
+            .map(|remailer| remailer.name[0].clone())
+            .collect()
+    };
+    assert_eq!(select(&["*"]), vec!["name1", "name2", "name3", "name5"]);
+    assert_eq!(select(&["*@test0.invalid"]), vec!["name2"]);
+    // A named remailer is selected even if disabled
+    assert_eq!(
+        select(&["name4", "*@test0.invalid"]),
+        vec!["name2", "name4"]
+    );
+    assert!(select_remailers(&["nam1".to_string()], &remailers).is_err());
+}
@@ -0,0 +1,496 @@
+use std::fs;
+use std::path::Path;
+
+use serde_json::json;
+
+use cypherpunk_cli::config::{
+    check_config, check_layers, check_schema, load_config, load_layered_config, load_layers,
+    read_config, set_enable, ConfigError, ConfigFormat, ConfigLayer, KeyError, Remailer,
+    EMBEDDED_CONFIG,
+};
+use cypherpunk_cli::migrate::{migrate, schema_version, CURRENT_SCHEMA};
+
+#[test]
+fn reports_every_problem_with_its_path() {
+    let config = json!({
+        "version": "test",
+        "authors": [],
+        "remailers": [
+            {"name": [], "email": "first@test.invalid", "enable": false, "key": ""},
+            {"name": ["second"], "email": "FIRST@test.invalid", "enable": false, "key": ""},
+            {"name": ["second", "third"], "email": "third@test.invalid", "enable": true, "key": "abc"},
+            {"name": ["fourth"], "email": "fourth", "enable": "yes", "key": 42}
+        ]
+    });
+    assert_eq!(
+        check_config(&config, Path::new(".")),
+        vec![
+            ConfigError::Type {
+                path: "$.remailers[3].enable".to_string(),
+                expected: "a boolean",
+            },
+            ConfigError::Type {
+                path: "$.remailers[3].key".to_string(),
+                expected: "a string",
+            },
+            ConfigError::EmptyNames {
+                path: "$.remailers[0].name".to_string(),
+            },
+            ConfigError::DuplicateEmail {
+                path: "$.remailers[1].email".to_string(),
+                email: "FIRST@test.invalid".to_string(),
+                first: "$.remailers[0].email".to_string(),
+            },
+            ConfigError::DuplicateAlias {
+                path: "$.remailers[2].name[0]".to_string(),
+                alias: "second".to_string(),
+                first: "$.remailers[1].name[0]".to_string(),
+            },
+            ConfigError::Key {
+                path: "$.remailers[2].key".to_string(),
+                error: KeyError::UnknownEncoding("second".to_string()),
+            },
+        ]
+    );
+}
+
+#[test]
+fn reports_missing_fields() {
+    let config = json!({"version": 2, "remailers": [{"name": ["first"]}]});
+    let errors: Vec<String> = check_schema(&config)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "$.version: expected a string",
+            "$.authors: missing field",
+            "$.remailers[0].email: missing field",
+            "$.remailers[0].enable: missing field",
+            "$.remailers[0].key: missing field",
+        ]
+    );
+}
+
+#[test]
+fn bad_keys_are_errors() {
+    let mut remailer = Remailer {
+        email: "remailer@test.invalid".to_string(),
+        ..Remailer::default()
+    };
+    assert_eq!(
+        remailer.as_key(),
+        Err(KeyError::Empty("remailer@test.invalid".to_string()))
+    );
+    remailer.key = "abc".to_string();
+    assert_eq!(
+        remailer.as_key(),
+        Err(KeyError::UnknownEncoding(
+            "remailer@test.invalid".to_string()
+        ))
+    );
+    remailer.key = "base64:!!".to_string();
+    assert!(matches!(
+        remailer.as_key(),
+        Err(KeyError::InvalidBase64(_, _))
+    ));
+    remailer.key = "base64:aGVsbG8=".to_string();
+    assert_eq!(remailer.as_key().unwrap(), b"hello");
+    assert!(remailer.fingerprint().is_err());
+}
+
+#[test]
+fn reads_every_key_encoding() {
+    let dir = tempfile::tempdir().unwrap();
+    let armored = "-----BEGIN PGP PUBLIC KEY BLOCK-----\n\naGVsbG8=\n=abcd\n-----END PGP PUBLIC KEY BLOCK-----\n";
+    fs::write(dir.path().join("key.asc"), armored).unwrap();
+    fs::write(dir.path().join("key.gpg"), b"hello").unwrap();
+    fs::create_dir(dir.path().join("keys")).unwrap();
+    fs::write(dir.path().join("keys/remailer@test.invalid.asc"), armored).unwrap();
+
+    let remailer = |key: &str| Remailer {
+        email: "Remailer@test.invalid".to_string(),
+        key: key.to_string(),
+        base_dir: Some(dir.path().to_path_buf()),
+        ..Remailer::default()
+    };
+    for key in &[
+        "base64:aGVsbG8=",
+        armored,
+        "file:key.asc",
+        "file:key.gpg",
+        "dir:keys",
+    ] {
+        assert_eq!(remailer(key).as_key().unwrap(), b"hello", "key {}", key);
+    }
+    assert!(matches!(
+        remailer("file:missing.asc").as_key(),
+        Err(KeyError::Unreadable(_, _, _))
+    ));
+    assert!(matches!(
+        remailer("aGVsbG8=").as_key(),
+        Err(KeyError::UnknownEncoding(_))
+    ));
+
+    // The key files are inlined, the other keys kept
+    for key in &["file:key.asc", "dir:keys"] {
+        let mut inlined = remailer(key);
+        inlined.inline_key().unwrap();
+        assert_eq!(inlined.key, "base64:aGVsbG8=");
+        assert_eq!(inlined.base_dir, None);
+    }
+    let mut kept = remailer(armored);
+    kept.inline_key().unwrap();
+    assert_eq!(kept, remailer(armored));
+    assert!(remailer("file:missing.asc").inline_key().is_err());
+}
+
+fn layer(origin: &str, value: serde_json::Value) -> ConfigLayer {
+    ConfigLayer {
+        origin: origin.to_string(),
+        path: None,
+        data: Vec::new(),
+        value,
+    }
+}
+
+#[test]
+fn overlays_disable_remailers_and_add_aliases() {
+    let list = |email: &str| {
+        json!({
+            "version": "test",
+            "authors": ["tests"],
+            "remailers": [
+                {"name": ["first"], "email": email, "enable": true, "key": "base64:"},
+                {"name": ["second"], "email": "second@test.invalid", "enable": true, "key": "base64:"}
+            ]
+        })
+    };
+    let layers = vec![
+        layer("embedded", list("old@test.invalid")),
+        layer("system", list("first@test.invalid")),
+        layer(
+            "user",
+            json!({"remailers": [
+                {"email": "FIRST@test.invalid", "name": ["one", "first"]},
+                {"email": "second@test.invalid", "enable": false},
+                {"name": ["third"], "email": "third@test.invalid", "enable": true, "key": "base64:"}
+            ]}),
+        ),
+    ];
+    let config = load_layered_config(&layers).unwrap();
+
+    // The system list replaces the embedded one, the user overlay changes it
+    let remailers: Vec<(&str, Vec<String>, bool)> = config
+        .remailers
+        .iter()
+        .map(|remailer| {
+            (
+                remailer.email.as_str(),
+                remailer.name.clone(),
+                remailer.enable,
+            )
+        })
+        .collect();
+    assert_eq!(
+        remailers,
+        vec![
+            (
+                "FIRST@test.invalid",
+                vec!["first".to_string(), "one".to_string()],
+                true
+            ),
+            ("second@test.invalid", vec!["second".to_string()], false),
+            ("third@test.invalid", vec!["third".to_string()], true),
+        ]
+    );
+
+    // An alias given to two remailers is refused
+    let mut layers = layers;
+    layers.push(layer(
+        "working directory",
+        json!({"remailers": [{"email": "third@test.invalid", "name": ["second"]}]}),
+    ));
+    let err = load_layered_config(&layers).unwrap_err();
+    assert_eq!(
+        err.find_root_cause().downcast_ref::<ConfigError>(),
+        Some(&ConfigError::DuplicateAlias {
+            path: "$.remailers[2].name[1]".to_string(),
+            alias: "second".to_string(),
+            first: "$.remailers[1].name[0]".to_string(),
+        })
+    );
+}
+
+#[test]
+fn reports_the_problems_of_each_layer() {
+    let list = json!({
+        "version": "test",
+        "authors": ["tests"],
+        "remailers": [
+            {"name": ["first"], "email": "first@test.invalid", "enable": false, "key": "base64:"},
+            {"name": ["second"], "email": "second", "enable": false, "key": "base64:"}
+        ]
+    });
+    let layers = vec![
+        layer("embedded", json!({"version": 1})),
+        layer("system", list),
+        layer(
+            "user",
+            json!({"remailers": [
+                {"email": "FIRST@test.invalid", "name": ["first", "one"]},
+                {"email": "third@test.invalid", "name": ["second"], "key": "abc"},
+                {"email": "fourth@test.invalid", "enable": "yes"},
+                {"name": ["fifth"]}
+            ]}),
+        ),
+    ];
+    let user = |error| ("user".to_string(), error);
+    assert_eq!(
+        check_layers(&layers),
+        vec![
+            (
+                "system".to_string(),
+                ConfigError::InvalidEmail {
+                    path: "$.remailers[1].email".to_string(),
+                    email: "second".to_string(),
+                }
+            ),
+            user(ConfigError::Type {
+                path: "$.remailers[2].enable".to_string(),
+                expected: "a boolean",
+            }),
+            user(ConfigError::Missing {
+                path: "$.remailers[3].email".to_string(),
+            }),
+            user(ConfigError::DuplicateAlias {
+                path: "$.remailers[1].name[0]".to_string(),
+                alias: "second".to_string(),
+                first: "$.remailers[1].name[0] in system".to_string(),
+            }),
+            user(ConfigError::Key {
+                path: "$.remailers[1].key".to_string(),
+                error: KeyError::UnknownEncoding("second".to_string()),
+            }),
+        ]
+    );
+}
+
+#[test]
+fn enables_remailers_in_overlays_and_lists() {
+    let mut overlay = json!({"remailers": [{"email": "first@test.invalid", "name": ["one"]}]});
+    set_enable(&mut overlay, "FIRST@test.invalid", false).unwrap();
+    set_enable(&mut overlay, "second@test.invalid", true).unwrap();
+    assert_eq!(
+        overlay,
+        json!({"remailers": [
+            {"email": "first@test.invalid", "name": ["one"], "enable": false},
+            {"email": "second@test.invalid", "enable": true}
+        ]})
+    );
+
+    let mut list = json!({"version": "test", "authors": [], "remailers": []});
+    assert!(set_enable(&mut list, "first@test.invalid", true).is_err());
+}
+
+#[test]
+fn loads_the_layers_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let system = dir.path().join("system.json");
+    let missing = dir.path().join("missing.json");
+    let explicit = dir.path().join("keys/explicit.json");
+    fs::create_dir(dir.path().join("keys")).unwrap();
+    fs::write(&system, r#"{"remailers": []}"#).unwrap();
+    fs::write(
+        &explicit,
+        r#"{"remailers": [{"email": "a@test.invalid", "key": "file:a.asc"}]}"#,
+    )
+    .unwrap();
+
+    let layers = load_layers(&[system.clone(), missing], Some(&explicit)).unwrap();
+    let origins: Vec<&str> = layers.iter().map(|layer| layer.origin.as_str()).collect();
+    assert_eq!(
+        origins,
+        vec![
+            "embedded",
+            system.to_str().unwrap(),
+            explicit.to_str().unwrap()
+        ]
+    );
+    assert!(layers[0].is_complete() && !layers[1].is_complete());
+    // The key files are relative to their config
+    assert_eq!(
+        layers[2].value["remailers"][0]["key"],
+        format!("file:{}", dir.path().join("keys/a.asc").to_str().unwrap())
+    );
+
+    let missing = load_layers(&[], Some(&dir.path().join("missing.json")));
+    assert!(matches!(missing, Err(ConfigError::Read { .. })));
+}
+
+#[test]
+fn migrates_old_configs() {
+    let mut config: serde_json::Value = serde_json::from_str(EMBEDDED_CONFIG).unwrap();
+    config["remailers"] = json!([config["remailers"][0], {"name": ["broken"], "email": "b@test.invalid", "enable": false, "key": "abc"}]);
+    let fingerprint = serde_json::from_value::<Remailer>(config["remailers"][0].clone())
+        .unwrap()
+        .fingerprint()
+        .unwrap();
+
+    assert_eq!(schema_version(&config), 1);
+    let applied = migrate(&mut config, Path::new("."), "test").unwrap();
+    assert_eq!(applied.len(), 1);
+    assert_eq!(schema_version(&config), CURRENT_SCHEMA);
+    assert_eq!(config.as_object().unwrap().keys().next().unwrap(), "schema");
+    assert_eq!(config["remailers"][0]["fingerprint"], fingerprint);
+    assert_eq!(config["remailers"][0]["capabilities"], json!([]));
+    assert!(config["remailers"][1].get("fingerprint").is_none());
+
+    // Up to date, or an overlay: nothing to do
+    assert!(migrate(&mut config, Path::new("."), "test")
+        .unwrap()
+        .is_empty());
+    let mut overlay = json!({"remailers": [{"email": "b@test.invalid", "enable": true}]});
+    assert!(migrate(&mut overlay, Path::new("."), "test")
+        .unwrap()
+        .is_empty());
+    assert!(overlay["remailers"][0].get("capabilities").is_none());
+}
+
+#[test]
+fn loads_old_configs_without_pinning() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config: serde_json::Value = serde_json::from_str(EMBEDDED_CONFIG).unwrap();
+    config["remailers"] = json!([config["remailers"][0]]);
+    let path = dir.path().join("old.json");
+    fs::write(&path, config.to_string()).unwrap();
+
+    let loaded = load_config(&path).unwrap();
+    assert_eq!(loaded.schema, 1);
+    assert_eq!(loaded.remailers[0].pinned_fingerprint, None);
+    let layers = load_layers(&[], Some(&path)).unwrap();
+    assert_eq!(layers[1].value, config);
+}
+
+#[test]
+fn refuses_the_duplicates_of_a_list_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config: serde_json::Value = serde_json::from_str(EMBEDDED_CONFIG).unwrap();
+    config["remailers"] = json!([config["remailers"][0], config["remailers"][0]]);
+    let path = dir.path().join("duplicates.json");
+    fs::write(&path, config.to_string()).unwrap();
+
+    let err = load_config(&path).unwrap_err();
+    assert!(matches!(
+        err.find_root_cause().downcast_ref::<ConfigError>(),
+        Some(ConfigError::DuplicateEmail { .. })
+    ));
+}
+
+#[test]
+fn refuses_newer_configs() {
+    let mut config = json!({"schema": CURRENT_SCHEMA + 1, "version": "future", "remailers": []});
+    assert_eq!(
+        migrate(&mut config, Path::new("."), "future.json"),
+        Err(ConfigError::TooNew {
+            file: "future.json".to_string(),
+            schema: CURRENT_SCHEMA + 1,
+            supported: CURRENT_SCHEMA,
+        })
+    );
+}
+
+#[test]
+fn refuses_keys_not_matching_their_fingerprint() {
+    let config: serde_json::Value = serde_json::from_str(EMBEDDED_CONFIG).unwrap();
+    let mut remailer: Remailer = serde_json::from_value(config["remailers"][0].clone()).unwrap();
+    let fingerprint = remailer.fingerprint().unwrap();
+
+    remailer.pinned_fingerprint = Some(fingerprint.to_lowercase());
+    assert!(remailer.as_key().is_ok());
+    remailer.pinned_fingerprint = Some("0000".to_string());
+    assert_eq!(
+        remailer.as_key(),
+        Err(KeyError::FingerprintMismatch(
+            remailer.label().to_string(),
+            "0000".to_string(),
+            fingerprint
+        ))
+    );
+}
+
+#[test]
+fn reads_toml_and_yaml_configs() {
+    let dir = tempfile::tempdir().unwrap();
+    let expected = json!({
+        "version": "test",
+        "authors": ["ops"],
+        "remailers": [
+            {"name": ["first", "1st"], "email": "first@test.invalid", "enable": true, "key": ""},
+            {"name": ["second"], "email": "second@test.invalid", "enable": false, "key": ""}
+        ]
+    });
+    let toml = r#"
+# Maintained by the ops team
+version = "test"
+authors = ["ops"]
+
+[[remailers]]
+name = ["first", "1st"]
+email = "first@test.invalid"
+enable = true
+key = ""
+
+[[remailers]]
+name = ["second"]
+email = "second@test.invalid"
+# Down since the last pinger report
+enable = false
+key = ""
+"#;
+    let yaml = r#"
+# Maintained by the ops team
+version: test
+authors: [ops]
+remailers:
+  - name: [first, 1st]
+    email: first@test.invalid
+    enable: true
+    key: ""
+  - name: [second]
+    email: second@test.invalid
+    enable: false # Down since the last pinger report
+    key: ""
+"#;
+    // By extension, then by content
+    for (name, text) in &[
+        ("remailers.toml", toml),
+        ("remailers.yml", yaml),
+        ("toml-list", toml),
+        ("yaml-list", yaml),
+    ] {
+        let path = dir.path().join(name);
+        fs::write(&path, text).unwrap();
+        assert_eq!(read_config(&path).unwrap(), expected, "{}", name);
+    }
+    assert_eq!(ConfigFormat::sniff(EMBEDDED_CONFIG), ConfigFormat::Json);
+
+    // Written back in each format, the config is the same
+    for format in &[ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml] {
+        let text = format.serialize(&expected).unwrap();
+        assert_eq!(format.parse(&text, "test").unwrap(), expected, "{}", format);
+    }
+
+    let path = dir.path().join("broken.toml");
+    fs::write(&path, "version = \"test\"\nauthors = [\n").unwrap();
+    match read_config(&path) {
+        Err(ConfigError::Syntax {
+            format, position, ..
+        }) => {
+            assert_eq!(format, ConfigFormat::Toml);
+            assert!(position.is_some());
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
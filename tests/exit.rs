@@ -0,0 +1,59 @@
+use std::io::{self, Write};
+
+use failure::{err_msg, Error, Fail, ResultExt};
+
+use cypherpunk_cli::config::ConfigError;
+use cypherpunk_cli::exit::{exit_code, write_out, ErrorKind, FAILURE};
+
+#[test]
+fn the_kind_of_an_error_gives_its_exit_code() {
+    let io: Result<(), Error> = Err(err_msg("Cannot write in file"));
+    let err: Error = io
+        .context("Failed to write message n°1")
+        .context(ErrorKind::Io)
+        .unwrap_err()
+        .into();
+    assert_eq!(ErrorKind::of(&err), Some(ErrorKind::Io));
+    assert_eq!(exit_code(&err), 6);
+
+    // The outermost kind is the one of the error
+    let partial: Error = Err::<(), Error>(err)
+        .context(ErrorKind::Partial)
+        .unwrap_err()
+        .into();
+    assert_eq!(exit_code(&partial), ErrorKind::Partial.exit_code());
+
+    let config: Error = ConfigError::Unsigned {
+        file: "remailers.json".to_string(),
+    }
+    .context("Refusing an unauthenticated config")
+    .into();
+    assert_eq!(exit_code(&config), ErrorKind::Config.exit_code());
+
+    assert_eq!(ErrorKind::of(&err_msg("Unknown")), None);
+    assert_eq!(exit_code(&err_msg("Unknown")), FAILURE);
+}
+
+/// A writer failing as stdout closed by `| head`
+struct ClosedOutput;
+
+impl Write for ClosedOutput {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn a_closed_output_is_an_io_error() {
+    let mut output = Vec::new();
+    write_out(&mut output, format_args!("{} remailers\n", 3)).unwrap();
+    assert_eq!(output, b"3 remailers\n");
+
+    let err = write_out(&mut ClosedOutput, format_args!("{} remailers\n", 3)).unwrap_err();
+    assert_eq!(ErrorKind::of(&err), Some(ErrorKind::Io));
+    assert_eq!(exit_code(&err), 6);
+}
@@ -0,0 +1,103 @@
+use cypherpunk_cli::config::{Remailer, RemailerStats};
+use cypherpunk_cli::list::{format_rows, iso_date, KeyStatus, ListFormat, RemailerRow};
+use cypherpunk_cli::pgp::packet;
+
+/// An old-format OpenPGP packet, with a one-byte length
+fn packet(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0x80 | tag << 2, body.len() as u8];
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// A (fake) RSA key of `email` created at `created`, its self-signature giving it an expiration
+/// time after its creation, if any
+fn key(email: &str, created: u32, expiration: Option<u32>) -> Vec<u8> {
+    let mut public = vec![4];
+    public.extend_from_slice(&created.to_be_bytes());
+    // RSA, a 9-bit modulus and a 2-bit exponent
+    public.extend_from_slice(&[1, 0, 9, 1, 1, 0, 2, 3]);
+
+    let mut subpackets = vec![5, 2];
+    subpackets.extend_from_slice(&created.to_be_bytes());
+    if let Some(expiration) = expiration {
+        subpackets.extend_from_slice(&[5, 9]);
+        subpackets.extend_from_slice(&expiration.to_be_bytes());
+    }
+    let mut signature = vec![4, 0x13, 1, 8, 0, subpackets.len() as u8];
+    signature.append(&mut subpackets);
+    signature.extend_from_slice(&[0, 0, 0xab, 0xcd, 0, 1, 1]);
+
+    let mut key = packet(6, &public);
+    key.append(&mut packet(13, format!("Test <{}>", email).as_bytes()));
+    key.append(&mut packet(2, &signature));
+    key
+}
+
+fn remailer(name: &str, key: &[u8]) -> Remailer {
+    Remailer {
+        name: vec![name.to_string(), format!("{}2", name)],
+        email: format!("{}@test.invalid", name),
+        enable: true,
+        key: format!("base64:{}", base64::encode(key)),
+        capabilities: vec!["cpunk".to_string(), "latent".to_string()],
+        ..Remailer::default()
+    }
+}
+
+#[test]
+fn reads_the_key_expiry() {
+    let created = 1_600_000_000;
+    assert_eq!(packet::expiry(&key("a", created, None)).unwrap(), None);
+    assert_eq!(
+        packet::expiry(&key("a", created, Some(86400))).unwrap(),
+        Some(u64::from(created) + 86400)
+    );
+    assert_eq!(iso_date(u64::from(created)), "2020-09-13");
+
+    let expiring = remailer(
+        "expiring",
+        &key("expiring@test.invalid", created, Some(86400)),
+    );
+    let row = RemailerRow::new(&expiring, u64::from(created));
+    assert_eq!(row.key, KeyStatus::Valid);
+    assert_eq!(row.key_expiry.as_deref(), Some("2020-09-14"));
+    assert_eq!(
+        RemailerRow::new(&expiring, u64::from(created) + 86400).key,
+        KeyStatus::Expired
+    );
+}
+
+#[test]
+fn formats_the_rows() {
+    let mut first = remailer("first", &key("first@test.invalid", 0, None));
+    first.stats = Some(RemailerStats {
+        latency: "0:12".to_string(),
+        uptime: "99.5%".to_string(),
+    });
+    let mut second = remailer("second", b"");
+    second.enable = false;
+    second.capabilities = Vec::new();
+    let rows = vec![RemailerRow::new(&first, 0), RemailerRow::new(&second, 0)];
+    assert_eq!(rows[1].key, KeyStatus::Invalid);
+
+    let table = format_rows(&rows, ListFormat::Table).unwrap();
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("ALIASES         EMAIL "));
+    assert!(lines[1].ends_with("never    0:12     99.5%"));
+    assert!(lines[2].contains("no       -             invalid  -"));
+
+    let csv = format_rows(&rows, ListFormat::CSV).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "aliases,email,enabled,capabilities,key,fingerprint,expires,latency,uptime"
+    );
+    assert!(lines[2].starts_with("second second2,second@test.invalid,no,,invalid,,,,"));
+
+    let json: serde_json::Value =
+        serde_json::from_str(&format_rows(&rows, ListFormat::JSON).unwrap()).unwrap();
+    assert_eq!(json[0]["latency"], "0:12");
+    assert_eq!(json[1]["key"], "invalid");
+    assert!(json[1]["key_error"].is_string());
+}
@@ -0,0 +1,386 @@
+#![cfg(feature = "test-backend")]
+
+use std::fs;
+use std::io::{Read, Write};
+use std::time::{Duration, SystemTime};
+
+use failure::Fallible;
+
+use cypherpunk_cli::config::{load_layers, verify_layers, ConfigError, Remailer, EMBEDDED_CONFIG};
+use cypherpunk_cli::format::{format_msg, EncryptedCopy, OutputFormat};
+use cypherpunk_cli::pgp::mock::{Call, MockBackend};
+use cypherpunk_cli::plan::Route;
+use cypherpunk_cli::remailer::read_envelope;
+use cypherpunk_cli::selftest::{selftest, STAGES};
+use cypherpunk_cli::server::{maildir_new, Server};
+use cypherpunk_cli::testnet::TestNetwork;
+use cypherpunk_cli::{Cypherpunk, CypherpunkCore, PGPBackend};
+
+const MESSAGE: &str = "::\nAnon-To: alice@example.org\n\nHello!\n";
+
+/// Make a core knowing the keys of the given remailers, with a handle on its backend
+fn core(emails: &[&str]) -> (CypherpunkCore<MockBackend>, MockBackend) {
+    let mock = MockBackend::new();
+    let core = CypherpunkCore::new(mock.clone());
+    let keys = emails
+        .iter()
+        .map(|email| MockBackend::public_key(email))
+        .collect();
+    core.import_keys(keys).unwrap();
+    (core, mock)
+}
+
+#[test]
+fn encrypts_each_layer_for_its_remailer() {
+    let (core, mock) = core(&["first@test.invalid", "last@test.invalid"]);
+    // The chain starts with the farther remailer
+    let chain = vec![
+        "last@test.invalid".to_string(),
+        "first@test.invalid".to_string(),
+    ];
+    let output = core
+        .encrypt_message(&chain, &["Inflate: 10".to_string()], MESSAGE.into())
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output
+        .starts_with("::\nInflate: 10\nAnon-To: first@test.invalid\n\n::\nEncrypted: PGP\n\n"));
+    assert_eq!(MockBackend::recipient(&output), Some("first@test.invalid"));
+
+    let calls = mock.calls();
+    assert_eq!(calls.len(), 4);
+    assert_eq!(
+        calls[2],
+        Call::Encrypt {
+            recipients: vec!["last@test.invalid".to_string()],
+            input: MESSAGE.into(),
+        }
+    );
+    match &calls[3] {
+        Call::Encrypt { recipients, input } => {
+            assert_eq!(recipients, &vec!["first@test.invalid".to_string()]);
+            assert!(input.starts_with(b"::\nInflate: 10\nAnon-To: last@test.invalid\n\n"));
+        }
+        other => panic!("Unexpected call {:?}", other),
+    }
+}
+
+#[test]
+fn fails_without_the_remailer_key() {
+    let (core, _) = core(&["first@test.invalid"]);
+    let chain = vec!["unknown@test.invalid".to_string()];
+    assert!(core.encrypt_message(&chain, &[], MESSAGE.into()).is_err());
+}
+
+#[test]
+fn formats_the_message_for_the_first_remailer() {
+    let (core, _) = core(&["first@test.invalid"]);
+    let chain = vec!["first@test.invalid".to_string()];
+    let output = core.encrypt_message(&chain, &[], MESSAGE.into()).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    let eml = format_msg(OutputFormat::EML, output.clone()).unwrap();
+    assert!(
+        eml.contains("To: first@test.invalid\n\n::\nEncrypted: PGP\n\n-----BEGIN PGP MESSAGE-----")
+    );
+    let mailto = format_msg(OutputFormat::Mailto, output.clone()).unwrap();
+    assert!(mailto.starts_with("mailto:first@test.invalid?body=%3A%3A%0AEncrypted%3A%20PGP"));
+    assert_eq!(
+        format_msg(OutputFormat::Cypherpunk, output.clone()).unwrap(),
+        output
+    );
+
+    // As listed by `--json`
+    let copy = EncryptedCopy::new(0, chain, OutputFormat::EML, eml.clone());
+    assert_eq!(
+        serde_json::to_value(&copy).unwrap(),
+        serde_json::json!({
+            "copy": 1,
+            "chain": ["first@test.invalid"],
+            "entry": "first@test.invalid",
+            "format": "eml",
+            "payload": eml,
+        })
+    );
+}
+
+#[test]
+fn delivers_through_the_test_network() {
+    let network = TestNetwork::with_mock(4).unwrap();
+    let remailers = &network.config().remailers;
+    let (core, _) = core(
+        &remailers
+            .iter()
+            .map(|remailer| remailer.email.as_str())
+            .collect::<Vec<&str>>(),
+    );
+    let chain: Vec<String> = remailers
+        .iter()
+        .rev()
+        .map(|remailer| remailer.email.clone())
+        .collect();
+    let route = Route::new(&chain, &["Latent-Time: +0:10".to_string()], remailers).unwrap();
+    let output = core.encrypt_route(&route.hops, MESSAGE.into()).unwrap();
+
+    for format in &[
+        OutputFormat::Cypherpunk,
+        OutputFormat::Mailto,
+        OutputFormat::EML,
+    ] {
+        let formatted = format_msg(*format, String::from_utf8(output.clone()).unwrap()).unwrap();
+        let peeled = network.assert_delivered(&formatted, "alice@example.org", "Hello!\n");
+        assert_eq!(peeled.steps.len(), 4);
+        assert_eq!(peeled.steps[0].remailer, "remailer4@test.invalid");
+        assert_eq!(peeled.steps[0].headers[0].to_string(), "Latent-Time: +0:10");
+    }
+}
+
+#[test]
+fn drops_the_address_mail_headers() {
+    let network = TestNetwork::with_mock(1).unwrap();
+    let (core, _) = core(&["remailer1@test.invalid"]);
+    let chain = vec!["remailer1@test.invalid".to_string()];
+    let route = Route::new(&chain, &[], &network.config().remailers).unwrap();
+    let message = "::\nAnon-To: alice@example.org\n\n##\nSubject: Test\nBcc: eve@example.org\n\
+                   to: eve@example.org\nResent-To: eve@example.org\nFrom: bob@example.org\n\
+                   X-Test: a\rCc: eve@example.org\n\nHello!\n";
+    let output = core.encrypt_route(&route.hops, message.into()).unwrap();
+
+    let peeled = network.assert_delivered(
+        &String::from_utf8(output).unwrap(),
+        "alice@example.org",
+        "Hello!\n",
+    );
+    let headers: Vec<String> = peeled
+        .delivery
+        .unwrap()
+        .headers
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(headers, vec!["Subject: Test"]);
+}
+
+#[test]
+fn drops_null_messages() {
+    let network = TestNetwork::with_mock(2).unwrap();
+    let remailers = &network.config().remailers;
+    let (core, _) = core(&["remailer1@test.invalid", "remailer2@test.invalid"]);
+    let chain = vec![
+        "remailer1@test.invalid".to_string(),
+        "remailer2@test.invalid".to_string(),
+    ];
+    let mut route = Route::new(&chain, &[], remailers).unwrap();
+    route.hops[1].headers.push("Null: yes".to_string());
+    let output = core.encrypt_route(&route.hops, MESSAGE.into()).unwrap();
+
+    let peeled = network
+        .deliver(&String::from_utf8(output).unwrap())
+        .unwrap();
+    assert!(peeled.steps.is_empty());
+    assert_eq!(peeled.delivery, None);
+}
+
+#[test]
+fn marks_the_maildir_emails_once_delivered() {
+    let network = TestNetwork::with_mock(1).unwrap();
+    let (core, _) = core(&["remailer1@test.invalid"]);
+    let chain = vec!["remailer1@test.invalid".to_string()];
+    let route = Route::new(&chain, &[], &network.config().remailers).unwrap();
+    let now = core.encrypt_route(&route.hops, MESSAGE.into()).unwrap();
+    // Through the remailer twice, the first time with a delay
+    let chain = vec![chain[0].clone(), chain[0].clone()];
+    let mut route = Route::new(&chain, &[], &network.config().remailers).unwrap();
+    route.hops[1].headers.push("Latent-Time: +1:00".to_string());
+    let later = core.encrypt_route(&route.hops, MESSAGE.into()).unwrap();
+
+    let maildir = tempfile::tempdir().unwrap();
+    let new = maildir.path().join("new");
+    fs::create_dir(&new).unwrap();
+    let sent = maildir.path().join("sent");
+    let deliver = format!("cat >> '{}'", sent.display());
+    let pgp = MockBackend::new();
+    pgp.generate_key("remailer1@test.invalid").unwrap();
+    let mut server = Server::new(pgp, &deliver);
+    for (name, message) in [("1.now", now), ("2.later", later)].iter() {
+        let (_, body) = read_envelope(&String::from_utf8_lossy(message)).unwrap();
+        let email = format!("From: bob@example.org\n\n{}", body);
+        let path = new.join(name);
+        fs::write(&path, &email).unwrap();
+        assert!(server.receive(&email, Some(&path)).unwrap().is_some());
+        assert!(server.is_queued(&path));
+    }
+
+    // Only the email without delay is delivered, the delayed one stays in `new`
+    let delivered = server.deliver_due().unwrap();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0].to, "alice@example.org");
+    assert!(fs::read_to_string(&sent).unwrap().contains("Hello!"));
+    assert_eq!(
+        maildir_new(maildir.path()).unwrap(),
+        vec![new.join("2.later")]
+    );
+    assert!(maildir.path().join("cur").join("1.now:2,S").is_file());
+    assert!(server.next_time().unwrap() > SystemTime::now() + Duration::from_secs(3500));
+}
+
+#[test]
+fn checks_the_remailers_capabilities() {
+    let mut remailers = vec![remailer("first"), remailer("last")];
+    remailers[0].capabilities = vec!["cpunk".to_string(), "pgp".to_string()];
+    let chain = vec![
+        "first@test.invalid".to_string(),
+        "last@test.invalid".to_string(),
+    ];
+    let route = Route::new(&chain, &["Inflate: 10".to_string()], &remailers).unwrap();
+    assert!(route
+        .check(&remailers)
+        .unwrap_err()
+        .to_string()
+        .contains("no `inflt` capability"));
+
+    let route = Route::new(&chain, &["X-Custom: 1".to_string()], &remailers).unwrap();
+    assert_eq!(route.check(&remailers).unwrap().len(), 2);
+}
+
+/// Describe an enabled remailer with a mock key
+fn remailer(name: &str) -> Remailer {
+    let email = format!("{}@test.invalid", name);
+    Remailer {
+        name: vec![name.to_string()],
+        key: format!(
+            "base64:{}",
+            base64::encode(&MockBackend::public_key(&email))
+        ),
+        email,
+        enable: true,
+        ..Remailer::default()
+    }
+}
+
+#[test]
+fn passes_the_selftest() {
+    let stages = selftest(|_| MockBackend::new());
+    let names: Vec<&str> = stages.iter().map(|stage| stage.name).collect();
+    assert_eq!(names, STAGES);
+    for stage in &stages {
+        assert!(stage.passed(), "{}: {:?}", stage.name, stage.outcome);
+    }
+}
+
+/// A backend unable to decrypt messages
+struct NoDecrypt(MockBackend);
+
+impl PGPBackend for NoDecrypt {
+    fn import_key(&self, key: Vec<u8>) -> Fallible<()> {
+        self.0.import_key(key)
+    }
+    fn encrypt(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        recipients: Vec<String>,
+    ) -> Fallible<()> {
+        self.0.encrypt(input, output, recipients)
+    }
+    fn generate_key(&self, user_id: &str) -> Fallible<Vec<u8>> {
+        self.0.generate_key(user_id)
+    }
+}
+
+#[test]
+fn stops_the_selftest_at_the_first_failure() {
+    let stages = selftest(|_| NoDecrypt(MockBackend::new()));
+    let names: Vec<&str> = stages.iter().map(|stage| stage.name).collect();
+    assert_eq!(names, ["import", "encrypt", "decrypt"]);
+    assert!(stages[1].passed());
+    assert!(!stages[2].passed());
+}
+
+#[test]
+fn verifies_the_config_signatures() {
+    let dir = tempfile::tempdir().unwrap();
+    let list = dir.path().join("list.json");
+    let overlay = dir.path().join("overlay.json");
+    let embedded = dir.path().join("embedded.json");
+    fs::write(
+        &list,
+        r#"{"version": "test", "authors": [], "remailers": []}"#,
+    )
+    .unwrap();
+    fs::write(
+        &overlay,
+        r#"{"remailers": [{"email": "a@test.invalid", "enable": false}]}"#,
+    )
+    .unwrap();
+    fs::write(&embedded, EMBEDDED_CONFIG).unwrap();
+    let mut layers = load_layers(&[list.clone(), overlay, embedded], None).unwrap();
+
+    let pgp = MockBackend::new();
+    pgp.import_key(MockBackend::public_key("signer@test.invalid"))
+        .unwrap();
+    let file = list.to_string_lossy().into_owned();
+    assert_eq!(
+        verify_layers(&layers, &pgp),
+        vec![ConfigError::Unsigned { file: file.clone() }]
+    );
+
+    // Signed by a trusted signer
+    let data = fs::read(&list).unwrap();
+    fs::write(
+        dir.path().join("list.json.sig"),
+        MockBackend::sign("signer@test.invalid", &data),
+    )
+    .unwrap();
+    assert_eq!(verify_layers(&layers, &pgp), vec![]);
+
+    // The bytes read are the ones verified: changing the file afterwards changes nothing, but
+    // tampered layer bytes are refused
+    fs::write(
+        &list,
+        r#"{"version": "evil", "authors": [], "remailers": []}"#,
+    )
+    .unwrap();
+    assert_eq!(verify_layers(&layers, &pgp), vec![]);
+    layers[1].data = fs::read(&list).unwrap();
+    assert!(matches!(
+        verify_layers(&layers, &pgp)[..],
+        [ConfigError::BadSignature { .. }]
+    ));
+
+    // Signed by an unknown signer
+    fs::write(
+        dir.path().join("list.json.sig"),
+        MockBackend::sign("evil@test.invalid", &layers[1].data),
+    )
+    .unwrap();
+    assert!(matches!(
+        verify_layers(&layers, &pgp)[..],
+        [ConfigError::BadSignature { .. }]
+    ));
+
+    // The key files, not covered by the signature, must be pinned
+    let keys = dir.path().join("keys.json");
+    fs::write(
+        &keys,
+        r#"{"remailers": [
+            {"email": "a@test.invalid", "key": "file:a.asc"},
+            {"email": "b@test.invalid", "key": "dir:keys", "fingerprint": "0123"}
+        ]}"#,
+    )
+    .unwrap();
+    let data = fs::read(&keys).unwrap();
+    fs::write(
+        dir.path().join("keys.json.sig"),
+        MockBackend::sign("signer@test.invalid", &data),
+    )
+    .unwrap();
+    let layers = load_layers(std::slice::from_ref(&keys), None).unwrap();
+    assert_eq!(
+        verify_layers(&layers, &pgp),
+        vec![ConfigError::UnpinnedKey {
+            file: keys.to_string_lossy().into_owned(),
+            path: "$.remailers[0].key".to_string(),
+        }]
+    );
+}
@@ -0,0 +1,126 @@
+use cypherpunk_cli::config::{RemailerConfig, EMBEDDED_CONFIG};
+use cypherpunk_cli::pubring::{
+    parse_conf, parse_type2, update_keys, update_remailers, Pubring, RemailerConf,
+};
+
+/// The keys of the first remailers of the embedded list, as a pinger's `pubring.asc`
+fn pubring_asc(count: usize) -> (RemailerConfig, String) {
+    let config: RemailerConfig = serde_json::from_str(EMBEDDED_CONFIG).unwrap();
+    let mut keyring = Vec::new();
+    for remailer in &config.remailers[..count] {
+        keyring.append(&mut remailer.as_key().unwrap());
+    }
+    let base64 = base64::encode(&keyring);
+    let lines: Vec<&str> = base64
+        .as_bytes()
+        .chunks(64)
+        .map(|line| std::str::from_utf8(line).unwrap())
+        .collect();
+    let text = format!(
+        "-----BEGIN PGP PUBLIC KEY BLOCK-----\nVersion: Echolot\n\n{}\n-----END PGP PUBLIC KEY BLOCK-----\n",
+        lines.join("\n")
+    );
+    (config, text)
+}
+
+#[test]
+fn parses_the_pingers_lines() {
+    assert_eq!(
+        parse_conf(
+            r#"$remailer{"austria"} = "<mixmaster@remailer.privacy.at> cpunk max pgp latent";"#
+        ),
+        Some(RemailerConf {
+            name: "austria".to_string(),
+            email: "mixmaster@remailer.privacy.at".to_string(),
+            capabilities: vec!["cpunk", "max", "pgp", "latent"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        })
+    );
+    assert_eq!(parse_conf("austria mixmaster@remailer.privacy.at"), None);
+
+    let type2 = parse_type2(
+        "dizum remailer@dizum.com 0f0a0c23aeb2a9e0d7e48d3e2e76d3b4 4:3.0 C 2021-01-01 2022-01-01",
+    )
+    .unwrap();
+    assert_eq!(
+        (type2.name.as_str(), type2.email.as_str()),
+        ("dizum", "remailer@dizum.com")
+    );
+    assert_eq!(parse_type2("-----Begin Mix Key-----"), None);
+}
+
+#[test]
+fn imports_the_remailers_of_a_pubring() {
+    let (config, asc) = pubring_asc(3);
+    let embedded = &config.remailers;
+    let mut pubring = Pubring::default();
+    pubring.read(&asc).unwrap();
+    pubring
+        .read(&format!(
+            "$remailer{{\"first\"}} = \"<{}> cpunk pgp latent\";\n$remailer{{\"second\"}} = \"<{}> eric\";\n",
+            embedded[0].email, embedded[1].email
+        ))
+        .unwrap();
+    pubring
+        .read(&format!(
+            "mix2 {} 0f0a0c23aeb2a9e0d7e48d3e2e76d3b4 2.0.4 MC\n",
+            embedded[1].email
+        ))
+        .unwrap();
+    assert_eq!(pubring.keys.len(), 3);
+
+    let remailers = pubring.remailers().unwrap();
+    assert_eq!(remailers.len(), 3);
+    for (remailer, expected) in remailers.iter().zip(embedded) {
+        assert_eq!(remailer.email, expected.email);
+        assert_eq!(remailer.as_key().unwrap(), expected.as_key().unwrap());
+        assert_eq!(
+            remailer.pinned_fingerprint,
+            Some(expected.fingerprint().unwrap())
+        );
+    }
+    assert_eq!(remailers[0].name[0], "first");
+    assert_eq!(remailers[0].capabilities, vec!["cpunk", "pgp", "latent"]);
+    assert_eq!(
+        remailers[1].name[..2],
+        ["second".to_string(), "mix2".to_string()]
+    );
+    // Not a Cypherpunk remailer
+    assert!(remailers[0].enable && !remailers[1].enable);
+
+    // Into a local list: the known remailers keep their `enable` and aliases
+    let mut local = embedded[..2].to_vec();
+    local[0].enable = false;
+    let updated = update_remailers(&local, &remailers);
+    assert_eq!(updated.len(), 3);
+    assert!(!updated[0].enable);
+    assert!(updated[0].name.starts_with(&local[0].name));
+    assert!(updated[0].name.contains(&"first".to_string()));
+    assert_eq!(updated[0].capabilities, remailers[0].capabilities);
+    assert_eq!(updated[2], remailers[2]);
+}
+
+#[test]
+fn imports_the_keys_of_known_remailers() {
+    let config: RemailerConfig = serde_json::from_str(EMBEDDED_CONFIG).unwrap();
+    let keys: Vec<Vec<u8>> = config.remailers[..3]
+        .iter()
+        .map(|remailer| remailer.as_key().unwrap())
+        .collect();
+    let mut local = config.remailers[..2].to_vec();
+    local[0].key = String::new();
+    local[0].pinned_fingerprint = None;
+
+    let (updated, unknown) = update_keys(&local, &keys).unwrap();
+    assert_eq!(updated.len(), 2);
+    assert_eq!(updated[0].as_key().unwrap(), keys[0]);
+    assert_eq!(
+        updated[0].pinned_fingerprint,
+        Some(config.remailers[0].fingerprint().unwrap())
+    );
+    assert_eq!(updated[1].as_key().unwrap(), keys[1]);
+    assert_eq!(unknown.len(), 1);
+    assert!(unknown[0].contains(&config.remailers[2].email));
+}
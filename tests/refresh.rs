@@ -0,0 +1,128 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use cypherpunk_cli::config::{RemailerConfig, RemailerStats, EMBEDDED_CONFIG};
+use cypherpunk_cli::refresh::{mbox_date, refresh, requests, split_mbox, to_mbox, Email};
+
+/// Armor the keys of some remailers of the embedded list, as in a `remailer-key` reply
+fn armored_keys(config: &RemailerConfig, indexes: &[usize]) -> String {
+    let mut keyring = Vec::new();
+    for &index in indexes {
+        keyring.append(&mut config.remailers[index].as_key().unwrap());
+    }
+    format!(
+        "-----BEGIN PGP PUBLIC KEY BLOCK-----\n\n{}\n-----END PGP PUBLIC KEY BLOCK-----\n",
+        base64::encode(&keyring)
+    )
+}
+
+fn email(from: &str, subject: &str, body: &str) -> Email {
+    Email {
+        from: from.to_string(),
+        subject: subject.to_string(),
+        body: body.to_string(),
+    }
+}
+
+#[test]
+fn reads_the_emails_of_an_mbox() {
+    let mbox = "From remailer@test.invalid Thu Jan  1 00:00:00 1970\n\
+                From: \"Test remailer\"\n <remailer@test.invalid>\n\
+                Subject: remailer-conf\n\
+                \n\
+                >From the remailer\n\
+                \n\
+                From other@test.invalid Thu Jan  1 00:00:00 1970\n\
+                From: other@test.invalid\n\
+                \n\
+                Body\n";
+    let emails: Vec<Email> = split_mbox(mbox)
+        .iter()
+        .filter_map(|text| Email::parse(text))
+        .collect();
+    assert_eq!(
+        emails,
+        vec![
+            email(
+                "remailer@test.invalid",
+                "remailer-conf",
+                "From the remailer\n\n"
+            ),
+            email("other@test.invalid", "", "Body\n"),
+        ]
+    );
+}
+
+#[test]
+fn refreshes_the_remailers_with_their_replies() {
+    let config: RemailerConfig = serde_json::from_str(EMBEDDED_CONFIG).unwrap();
+    let local = config.remailers[..3].to_vec();
+    let (first, second, third) = (&local[0].email, &local[1].email, &local[2].email);
+    let emails = vec![
+        // The key for the remailer's email is picked
+        email(first, "remailer-key", &armored_keys(&config, &[1, 0])),
+        email(
+            first,
+            "remailer-conf",
+            &format!(
+                "Remailer-Type: Mixmaster\n$remailer{{\"first\"}} = \"<{}> cpunk pgp latent\";\n",
+                first
+            ),
+        ),
+        email(second, "remailer-key", &armored_keys(&config, &[2])),
+        email(
+            third,
+            "remailer-stats",
+            &format!("third {} ++++++++++++ 18:31 99.95%\n", third),
+        ),
+        email("nobody@test.invalid", "remailer-key", ""),
+    ];
+    let (remailers, notes) = refresh(&local, &emails);
+
+    assert_eq!(remailers[0].as_key().unwrap(), local[0].as_key().unwrap());
+    assert_eq!(
+        remailers[0].pinned_fingerprint,
+        Some(local[0].fingerprint().unwrap())
+    );
+    assert_eq!(remailers[0].capabilities, vec!["cpunk", "pgp", "latent"]);
+    assert_eq!(remailers[1], local[1]);
+    assert_eq!(
+        remailers[2].stats,
+        Some(RemailerStats {
+            latency: "18:31".to_string(),
+            uptime: "99.95%".to_string(),
+        })
+    );
+    assert_eq!(notes.len(), 3, "{:?}", notes);
+    assert!(notes[0].contains("not for its email"));
+    assert!(notes[2].contains("not a known remailer"));
+}
+
+#[test]
+fn writes_the_requests_as_an_mbox() {
+    let config: RemailerConfig = serde_json::from_str(EMBEDDED_CONFIG).unwrap();
+    let deliveries = requests(&config.remailers[..2], Some("me@test.invalid"));
+    assert_eq!(deliveries.len(), 4);
+    assert_eq!(deliveries[1].to, config.remailers[0].email);
+
+    let texts = split_mbox(&to_mbox(&deliveries));
+    assert_eq!(texts.len(), 4);
+    let subjects: Vec<String> = texts
+        .iter()
+        .map(|text| Email::parse(text).unwrap().subject)
+        .collect();
+    assert_eq!(
+        subjects,
+        vec![
+            "remailer-key",
+            "remailer-conf",
+            "remailer-key",
+            "remailer-conf"
+        ]
+    );
+
+    assert_eq!(mbox_date(UNIX_EPOCH), "Thu Jan  1 00:00:00 1970");
+    assert_eq!(
+        mbox_date(UNIX_EPOCH + Duration::from_secs(1_600_000_000)),
+        "Sun Sep 13 12:26:40 2020"
+    );
+}
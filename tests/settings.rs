@@ -0,0 +1,32 @@
+use std::fs;
+
+use cypherpunk_cli::format::OutputFormat;
+use cypherpunk_cli::settings::load_settings;
+
+#[test]
+fn later_settings_replace_the_profiles() {
+    let dir = tempfile::tempdir().unwrap();
+    let system = dir.path().join("settings.toml");
+    fs::write(
+        &system,
+        "[profile.default]\nhops = 3\n\n[profile.safe]\nchain = [\"*@eu\", \"*\", \"dizum\"]\nredundancy = 3\nformat = \"eml\"\n",
+    )
+    .unwrap();
+    let user = dir.path().join("settings.json");
+    fs::write(&user, r#"{"profile": {"default": {"hops": 2}}}"#).unwrap();
+
+    let settings = load_settings(&[system.clone(), user]).unwrap();
+    assert_eq!(settings.profile("default").unwrap().hops, Some(2));
+    let safe = settings.profile("safe").unwrap();
+    assert_eq!(safe.redundancy, Some(3));
+    assert_eq!(safe.format().unwrap(), Some(OutputFormat::EML));
+    assert_eq!(settings.named_chains()["safe"], vec!["*@eu", "*", "dizum"]);
+    assert!(settings
+        .profile("fast")
+        .unwrap_err()
+        .to_string()
+        .contains("known profiles: default, safe"));
+
+    fs::write(&system, "[profile.typo]\nhop = 3\n").unwrap();
+    assert!(load_settings(&[system]).is_err());
+}
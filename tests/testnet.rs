@@ -0,0 +1,161 @@
+#![cfg(feature = "back-gpg")]
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use cypherpunk_cli::chain::make_chain;
+use cypherpunk_cli::config::remailer_map;
+use cypherpunk_cli::format::{format_msg, OutputFormat};
+use cypherpunk_cli::pgp::gpg::{self, GPGBackend};
+use cypherpunk_cli::plan::Route;
+use cypherpunk_cli::selftest::selftest;
+use cypherpunk_cli::testnet::TestNetwork;
+use cypherpunk_cli::{Cypherpunk, CypherpunkCore};
+
+const MESSAGE: &str =
+    "::\nAnon-To: alice@example.org\n\n##\nSubject: Test\n\nHello through the remailers!\n";
+const BODY: &str = "Hello through the remailers!\n";
+
+/// Return if a supported GnuPG is on the PATH, the tests being skipped otherwise
+fn has_gpg() -> bool {
+    match gpg::version().and_then(|line| gpg::check_version(&line)) {
+        Ok(_) => true,
+        Err(err) => {
+            eprintln!("Test skipped: {}", err);
+            false
+        }
+    }
+}
+
+/// Make a client core knowing the keys of the network
+fn client(network: &TestNetwork<GPGBackend>) -> CypherpunkCore<GPGBackend> {
+    let core = CypherpunkCore::new(GPGBackend::new(None, true));
+    let keys = network
+        .config()
+        .remailers
+        .iter()
+        .map(|remailer| remailer.as_key().unwrap())
+        .collect();
+    core.import_keys(keys).unwrap();
+    core
+}
+
+/// Encrypt the test message for a route
+fn encrypt(core: &CypherpunkCore<GPGBackend>, route: &Route) -> String {
+    String::from_utf8(core.encrypt_route(&route.hops, MESSAGE.into()).unwrap()).unwrap()
+}
+
+#[test]
+fn delivers_through_a_chain() {
+    if !has_gpg() {
+        return;
+    }
+    let network = TestNetwork::with_gpg(3).unwrap();
+    let core = client(&network);
+    let chain = network
+        .config()
+        .remailers
+        .iter()
+        .map(|remailer| remailer.email.clone());
+    let route = Route::new(
+        &chain.collect::<Vec<String>>(),
+        &[],
+        &network.config().remailers,
+    )
+    .unwrap();
+
+    let peeled = network.assert_delivered(&encrypt(&core, &route), "alice@example.org", BODY);
+    let path: Vec<&str> = peeled.steps.iter().map(|step| step.to.as_str()).collect();
+    assert_eq!(
+        path,
+        vec![
+            "remailer2@test.invalid",
+            "remailer3@test.invalid",
+            "alice@example.org"
+        ]
+    );
+    assert_eq!(
+        peeled.delivery.unwrap().headers[0].to_string(),
+        "Subject: Test"
+    );
+}
+
+#[test]
+fn honours_per_hop_headers() {
+    if !has_gpg() {
+        return;
+    }
+    let network = TestNetwork::with_gpg(2).unwrap();
+    let core = client(&network);
+    let chain = vec![
+        "remailer1@test.invalid".to_string(),
+        "remailer2@test.invalid".to_string(),
+    ];
+    let mut route = Route::new(&chain, &[], &network.config().remailers).unwrap();
+    route.hops[1].headers.push("Inflate: 10".to_string());
+    route.hops[1].delay = Some("+0:30".to_string());
+
+    let peeled = network.assert_delivered(&encrypt(&core, &route), "alice@example.org", BODY);
+    // The first remailer reads the headers of the second hop
+    let headers: Vec<String> = peeled.steps[0]
+        .headers
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(headers, vec!["Inflate: 10", "Latent-Time: +0:30"]);
+    assert!(peeled.steps[1].headers.is_empty());
+}
+
+#[test]
+fn delivers_every_output_format() {
+    if !has_gpg() {
+        return;
+    }
+    let network = TestNetwork::with_gpg(2).unwrap();
+    let core = client(&network);
+    let chain = vec![
+        "remailer2@test.invalid".to_string(),
+        "remailer1@test.invalid".to_string(),
+    ];
+    let route = Route::new(&chain, &[], &network.config().remailers).unwrap();
+    let message = encrypt(&core, &route);
+
+    for format in &[
+        OutputFormat::Cypherpunk,
+        OutputFormat::Mailto,
+        OutputFormat::EML,
+    ] {
+        let formatted = format_msg(*format, message.clone()).unwrap();
+        network.assert_delivered(&formatted, "alice@example.org", BODY);
+    }
+}
+
+#[test]
+fn delivers_redundant_copies() {
+    if !has_gpg() {
+        return;
+    }
+    let network = TestNetwork::with_gpg(3).unwrap();
+    let core = client(&network);
+    let remailers = &network.config().remailers;
+    let remmap = remailer_map(remailers.clone());
+    let mut rng = StdRng::seed_from_u64(2020);
+    let jokers = vec!["*".to_string(); 3];
+
+    for _ in 0..3 {
+        let chain = make_chain(&jokers, &remmap, remailers, false, &mut rng).unwrap();
+        let route = Route::new(&chain, &[], remailers).unwrap();
+        let peeled = network.assert_delivered(&encrypt(&core, &route), "alice@example.org", BODY);
+        assert_eq!(peeled.steps.len(), 3);
+    }
+}
+
+#[test]
+fn passes_the_selftest() {
+    if !has_gpg() {
+        return;
+    }
+    for stage in selftest(|homedir| GPGBackend::with_homedir(homedir.to_path_buf(), None, true)) {
+        assert!(stage.passed(), "{}: {:?}", stage.name, stage.outcome);
+    }
+}
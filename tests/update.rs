@@ -0,0 +1,101 @@
+use cypherpunk_cli::config::{Remailer, RemailerConfig, EMBEDDED_CONFIG};
+use cypherpunk_cli::update::{apply, diff, Change, MergePolicy};
+
+/// A local list of four remailers, the first one disabled and with a local alias, and a new list
+/// where the key of the second changed, the email of the third changed, the fourth is removed
+/// and a fifth is added
+fn lists() -> (Vec<Remailer>, Vec<Remailer>) {
+    let config: RemailerConfig = serde_json::from_str(EMBEDDED_CONFIG).unwrap();
+    let embedded = config.remailers;
+    let mut local = embedded[..4].to_vec();
+    local[0].enable = false;
+    local[0].name.push("mine".to_string());
+
+    let mut new = embedded[..3].to_vec();
+    new[1].key = embedded[3].key.clone();
+    new[2].email = "moved@test.invalid".to_string();
+    new.push(embedded[4].clone());
+    (local, new)
+}
+
+#[test]
+fn reports_the_changes_of_a_new_list() {
+    let (local, new) = lists();
+    assert_eq!(
+        diff(&local, &new),
+        vec![
+            Change::Key {
+                email: local[1].email.clone(),
+                old: Some(local[1].fingerprint().unwrap()),
+                new: Some(local[3].fingerprint().unwrap()),
+            },
+            Change::Email {
+                old: local[2].email.clone(),
+                new: "moved@test.invalid".to_string(),
+            },
+            Change::Added {
+                email: new[3].email.clone(),
+                name: new[3].name.clone(),
+            },
+            Change::Removed {
+                email: local[3].email.clone(),
+            },
+        ]
+    );
+    assert!(diff(&local, &local).is_empty());
+}
+
+#[test]
+fn applies_the_accepted_changes_only() {
+    let (local, new) = lists();
+    let changes = diff(&local, &new);
+
+    // Everything but the key and email changes and the added remailer
+    let accepted: Vec<Change> = changes
+        .iter()
+        .filter(|change| MergePolicy::Safe.accepts(change) == Some(true))
+        .cloned()
+        .collect();
+    assert_eq!(accepted.len(), 1);
+    let merged = apply(&local, &new, &accepted);
+    let emails: Vec<&str> = merged
+        .iter()
+        .map(|remailer| remailer.email.as_str())
+        .collect();
+    assert_eq!(
+        emails,
+        vec![
+            local[0].email.as_str(),
+            local[1].email.as_str(),
+            local[2].email.as_str(),
+        ]
+    );
+    // The local overrides are kept, the refused key and email too
+    assert!(!merged[0].enable);
+    assert!(merged[0].name.contains(&"mine".to_string()));
+    assert_eq!(merged[1].key, local[1].key);
+
+    // Nothing accepted: the local list
+    assert_eq!(apply(&local, &new, &[]), local);
+    assert_eq!(apply(&local, &new, &changes).len(), 4);
+}
+
+#[test]
+fn asks_for_the_keys_of_the_replies() {
+    let (local, new) = lists();
+    let changes = diff(&local, &new);
+    let key = &changes[0];
+    assert!(matches!(key, Change::Key { .. }));
+
+    assert_eq!(MergePolicy::All.accepts(key), Some(true));
+    assert_eq!(MergePolicy::All.accepts_reply(key), None);
+    assert_eq!(MergePolicy::Safe.accepts_reply(key), Some(false));
+    assert_eq!(MergePolicy::Report.accepts_reply(key), Some(false));
+    // The other changes of the replies are accepted as the ones of a list
+    for change in &changes[1..] {
+        assert_eq!(
+            MergePolicy::All.accepts_reply(change),
+            MergePolicy::All.accepts(change)
+        );
+    }
+}